@@ -0,0 +1,29 @@
+extern crate spsc;
+
+use std::env;
+use std::process;
+use std::time::Duration;
+
+use spsc::demos::barber::{run, Config};
+
+// cargo run --example barber -- [chairs] [mean arrival interval in ms]
+fn main() {
+	let args: Vec<String> = env::args().collect();
+	let chairs = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(3);
+	let interval = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(2);
+
+	let config = Config { chairs, arrival_interval: Duration::from_millis(interval), ..Config::default() };
+	let report = match run(&config) {
+		Ok(report) => report,
+		Err(e) => {
+			eprintln!("{}", e);
+			process::exit(1);
+		}
+	};
+
+	println!("{} customers, {} chairs, one arrival every {:?} on average, haircuts take {:?}",
+		config.customers, config.chairs, config.arrival_interval, config.haircut);
+	println!("  served:      {}", report.served);
+	println!("  turned away: {}", report.turned_away);
+	println!("took {:?}", report.elapsed);
+}
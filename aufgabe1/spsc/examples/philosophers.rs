@@ -0,0 +1,36 @@
+extern crate spsc;
+
+use std::env;
+use std::process;
+
+use spsc::demos::philosophers::{run, Config, Mode};
+
+// cargo run --example philosophers -- [naive|ordered|butler] [philosophers]
+fn main() {
+	let args: Vec<String> = env::args().collect();
+
+	let mode = match args.get(1).map(|s| s.as_str()) {
+		Some("naive") => Mode::Naive,
+		Some("butler") => Mode::Butler,
+		_ => Mode::Ordered,
+	};
+	let philosophers = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(5);
+
+	let config = Config { mode, philosophers, ..Config::default() };
+	let report = match run(&config) {
+		Ok(report) => report,
+		Err(e) => {
+			eprintln!("{}", e);
+			process::exit(1);
+		}
+	};
+
+	println!("{:?} with {} philosophers:", mode, philosophers);
+	for (i, meals) in report.meals_eaten.iter().enumerate() {
+		println!("  philosopher {} ate {} of {} meals", i, meals, config.meals);
+	}
+	if report.deadlocked {
+		println!("deadlock: nobody got a second fork within {:?}", config.patience);
+	}
+	println!("took {:?}", report.elapsed);
+}
@@ -0,0 +1,22 @@
+extern crate spsc;
+
+use std::env;
+
+use spsc::demos::readers_writers::{run, Config};
+use spsc::rwlock::Policy;
+
+// cargo run --example readers_writers -- [readers] [writers]
+fn main() {
+	let args: Vec<String> = env::args().collect();
+	let readers = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(8);
+	let writers = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(2);
+
+	for &policy in &[Policy::ReaderPreference, Policy::WriterPreference, Policy::Fair] {
+		let config = Config { readers, writers, policy, ..Config::default() };
+		let report = run(&config);
+
+		println!("{:?}:", policy);
+		println!("  reads:  {:10.1}/s, longest wait {:?}", report.reads_per_sec(), report.max_read_wait);
+		println!("  writes: {:10.1}/s, longest wait {:?}", report.writes_per_sec(), report.max_write_wait);
+	}
+}
@@ -0,0 +1,301 @@
+//! `Consumer::map()` and `Consumer::filter()`, for shaping messages on the
+//! receiving side without an extra thread and channel in between. The
+//! function runs in the thread that receives, after the value left the
+//! queue, so a slow function slows down the consumer and nothing else.
+//!
+//! Both wrappers offer the same receiving methods as the consumer and report
+//! the same errors. A value that `filter()` drops is gone, it is not handed
+//! to another consumer of the channel.
+//!
+//! `Consumer::tee()` on the other hand does need a thread, which copies every
+//! value into two new bounded channels. What happens when one of them is full
+//! is up to the caller, see `Branch`.
+
+use std::thread;
+use std::time::Duration;
+
+use super::{sync_channel, Consumer, Producer, RecvError, RecvTimeoutError, TryRecvError, TrySendError};
+
+/// A consumer that applies a function to every value, see `Consumer::map()`.
+pub struct Map<T: Send, F> {
+	inner: Consumer<T>,
+	f: F,
+}
+
+/// A consumer that skips values, see `Consumer::filter()`.
+pub struct Filter<T: Send, P> {
+	inner: Consumer<T>,
+	predicate: P,
+}
+
+/// How a branch of `Consumer::tee()` buffers its copies, with room for how
+/// many.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Branch {
+	/// A full branch makes the tee thread wait, which holds back the other
+	/// branch and, once the source is full as well, the producers. For
+	/// branches that must see everything.
+	Block(usize),
+	/// Copies that do not fit are dropped, the branch just misses them. For
+	/// taps like monitoring that must never slow the main branch down.
+	Lossy(usize),
+}
+
+impl<T: Send> Consumer<T> {
+	/// Turns the consumer into one that hands out `f(value)` instead of
+	/// `value`.
+	pub fn map<U, F: FnMut(T) -> U>(self, f: F) -> Map<T, F> {
+		Map { inner: self, f }
+	}
+
+	/// Turns the consumer into one that only hands out the values `predicate`
+	/// returns true for. The others are received and dropped.
+	pub fn filter<P: FnMut(&T) -> bool>(self, predicate: P) -> Filter<T, P> {
+		Filter { inner: self, predicate }
+	}
+
+	/// Splits the stream in two: a thread receives every value and sends a
+	/// copy into each of the returned consumers, whose channels are bounded
+	/// as `first` and `second` say. A branch that is dropped stops getting
+	/// copies, the other one carries on. When the stream ends, both branches
+	/// end with the same error.
+	pub fn tee(self, first: Branch, second: Branch) -> (Consumer<T>, Consumer<T>) where T: Clone + 'static {
+		let (px1, cx1) = branch_channel(first);
+		let (px2, cx2) = branch_channel(second);
+
+		thread::spawn(move || {
+			let mut branches = vec![px1, px2];
+			loop {
+				match self.recv() {
+					Ok(value) => {
+						branches.retain(|&(ref px, lossy)| {
+							if lossy {
+								match px.try_send(value.clone()) {
+									Ok(()) | Err(TrySendError::Full(_)) => true,
+									Err(_) => false,
+								}
+							} else {
+								px.send(value.clone()).is_ok()
+							}
+						});
+						if branches.is_empty() {
+							break;
+						}
+					}
+					Err(e) => {
+						for (px, _) in &branches {
+							px.pass_on(&e);
+						}
+						break;
+					}
+				}
+			}
+		});
+		(cx1, cx2)
+	}
+}
+
+// The channel for one branch of tee(), and whether it drops what does not
+// fit.
+fn branch_channel<T: Send>(branch: Branch) -> ((Producer<T>, bool), Consumer<T>) {
+	let (bound, lossy) = match branch {
+		Branch::Block(bound) => (bound, false),
+		Branch::Lossy(bound) => (bound, true),
+	};
+	let (px, cx) = sync_channel(bound);
+	((px, lossy), cx)
+}
+
+impl<T: Send, U, F: FnMut(T) -> U> Map<T, F> {
+	pub fn recv(&mut self) -> Result<U, RecvError> {
+		self.inner.recv().map(&mut self.f)
+	}
+
+	pub fn try_recv(&mut self) -> Result<U, TryRecvError> {
+		self.inner.try_recv().map(&mut self.f)
+	}
+
+	pub fn recv_timeout(&mut self, timeout: Duration) -> Result<U, RecvTimeoutError> {
+		self.inner.recv_timeout(timeout).map(&mut self.f)
+	}
+
+	/// Gives back the consumer underneath.
+	pub fn into_inner(self) -> Consumer<T> {
+		self.inner
+	}
+}
+
+impl<T: Send, P: FnMut(&T) -> bool> Filter<T, P> {
+	pub fn recv(&mut self) -> Result<T, RecvError> {
+		loop {
+			let value = self.inner.recv()?;
+			if (self.predicate)(&value) {
+				return Ok(value);
+			}
+		}
+	}
+
+	/// Skips over everything queued that does not match, so `Empty` means
+	/// that nothing matching is queued right now.
+	pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+		loop {
+			let value = self.inner.try_recv()?;
+			if (self.predicate)(&value) {
+				return Ok(value);
+			}
+		}
+	}
+
+	/// The timeout covers the whole call, not every value that gets skipped.
+	pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+		let deadline = self.inner.now() + timeout;
+		loop {
+			let left = deadline.saturating_duration_since(self.inner.now());
+			let value = self.inner.recv_timeout(left)?;
+			if (self.predicate)(&value) {
+				return Ok(value);
+			}
+		}
+	}
+
+	/// Gives back the consumer underneath.
+	pub fn into_inner(self) -> Consumer<T> {
+		self.inner
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::channel;
+	use super::super::shutdown::{attach, ShutdownToken};
+
+	#[test]
+	fn map_and_filter_shape_the_stream() {
+		let (px, cx) = channel::<u32>(16);
+		let producer_thread = thread::spawn(move || {
+			for i in 0..100 {
+				px.send(i).unwrap();
+			}
+		});
+
+		let mut evens = cx.filter(|v| v % 2 == 0);
+		let mut received = Vec::new();
+		while let Ok(v) = evens.recv() {
+			received.push(v);
+		}
+		producer_thread.join().unwrap();
+		assert_eq!(received, (0..100).filter(|v| v % 2 == 0).collect::<Vec<_>>());
+		assert_eq!(evens.try_recv(), Err(TryRecvError::Disconnected));
+
+		let (px, cx) = channel::<u32>(4);
+		let mut names = cx.map(|v| format!("#{}", v));
+		px.send(3).unwrap();
+		assert_eq!(names.recv(), Ok("#3".to_string()));
+		assert_eq!(names.try_recv(), Err(TryRecvError::Empty));
+	}
+
+	#[test]
+	fn filter_timeout_covers_skipped_values() {
+		let (px, cx) = channel::<u32>(4);
+		let mut odd = cx.filter(|v| v % 2 == 1);
+		px.send(2).unwrap();
+		px.send(4).unwrap();
+		assert_eq!(odd.try_recv(), Err(TryRecvError::Empty));
+		assert_eq!(odd.recv_timeout(Duration::from_millis(10)), Err(RecvTimeoutError::Timeout));
+		px.send(5).unwrap();
+		assert_eq!(odd.recv_timeout(Duration::from_millis(10)), Ok(5));
+		assert_eq!(odd.into_inner().size().unwrap(), 0);
+	}
+
+	#[test]
+	fn tee_gives_both_branches_everything() {
+		let (px, cx) = sync_channel::<u32>(4);
+		let (fast, slow) = cx.tee(Branch::Block(4), Branch::Block(4));
+
+		let producer_thread = thread::spawn(move || {
+			for i in 0..100 {
+				px.send(i).unwrap();
+			}
+		});
+		let slow_thread = thread::spawn(move || {
+			let mut received = Vec::new();
+			while let Ok(v) = slow.recv() {
+				if v % 10 == 0 {
+					thread::sleep(Duration::from_millis(1));
+				}
+				received.push(v);
+			}
+			received
+		});
+
+		let mut received = Vec::new();
+		while let Ok(v) = fast.recv() {
+			received.push(v);
+		}
+		producer_thread.join().unwrap();
+		assert_eq!(received, (0..100).collect::<Vec<_>>());
+		assert_eq!(slow_thread.join().unwrap(), received);
+	}
+
+	#[test]
+	fn a_stalled_lossy_tap_does_not_hold_back_the_other_branch() {
+		let (px, cx) = sync_channel::<u32>(2);
+		let (stalled, main) = cx.tee(Branch::Lossy(2), Branch::Block(2));
+
+		let producer_thread = thread::spawn(move || {
+			for i in 0..10 {
+				px.send(i).unwrap();
+			}
+		});
+		let received: Vec<_> = (0..10).map(|_| main.recv().unwrap()).collect();
+		producer_thread.join().unwrap();
+		assert_eq!(received, (0..10).collect::<Vec<_>>());
+		// the tap kept what fit and missed the rest
+		assert_eq!(stalled.try_recv(), Ok(0));
+		assert_eq!(stalled.try_recv(), Ok(1));
+		assert_eq!(stalled.recv(), Err(RecvError::Disconnected));
+	}
+
+	#[test]
+	fn tee_passes_on_how_the_stream_ended() {
+		let (px, cx) = channel::<u32>(4);
+		let (left, right) = cx.tee(Branch::Block(4), Branch::Block(4));
+		let producer_thread = px.spawn(|px| {
+			px.send(1).unwrap();
+			panic!("sensor on fire");
+		});
+		assert!(producer_thread.join().is_err());
+
+		let panicked = Err(RecvError::ProducerPanicked("sensor on fire".to_string()));
+		assert_eq!(left.recv(), Ok(1));
+		assert_eq!(left.recv(), panicked);
+		assert_eq!(right.recv(), Ok(1));
+		assert_eq!(right.recv(), panicked);
+
+		let token = ShutdownToken::new();
+		let (px, cx) = channel::<u32>(4);
+		attach(&cx, &token);
+		let (left, right) = cx.tee(Branch::Block(4), Branch::Block(4));
+		token.trigger();
+		assert_eq!(left.recv(), Err(RecvError::Shutdown));
+		assert_eq!(right.recv(), Err(RecvError::Shutdown));
+		drop(px);
+	}
+
+	#[test]
+	fn tee_survives_a_dropped_branch() {
+		let (px, cx) = channel::<u32>(4);
+		let (tap, main) = cx.tee(Branch::Lossy(1), Branch::Block(16));
+		drop(tap);
+
+		for i in 0..10 {
+			px.send(i).unwrap();
+		}
+		drop(px);
+		let received: Vec<_> = (0..10).map(|_| main.recv().unwrap()).collect();
+		assert_eq!(received, (0..10).collect::<Vec<_>>());
+		assert_eq!(main.recv(), Err(RecvError::Disconnected));
+	}
+}
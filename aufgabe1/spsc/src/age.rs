@@ -0,0 +1,133 @@
+//! How long values sit in a channel. Once `track()` was called on a channel,
+//! every value gets a timestamp when it becomes visible to the consumers,
+//! `head_age()` tells how long the oldest queued value has been waiting, and
+//! `queue_times()` sums up the time in queue of the last `SAMPLES` values
+//! that were received.
+//!
+//! A long queue whose head is young is busy but moving, a short one with an
+//! old head is stalled. The length alone cannot tell the two apart.
+//!
+//! Timestamps come from the channel's clock, see clock.rs.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::{Consumer, Producer};
+
+/// How many received values `queue_times()` looks at.
+pub const SAMPLES: usize = 1024;
+
+pub(crate) struct Ages {
+	// one per queued value, front first
+	enqueued: VecDeque<Instant>,
+	// time in queue of the last SAMPLES received values
+	waited: VecDeque<Duration>,
+}
+
+impl Ages {
+	pub(crate) fn published(&mut self, n: usize, now: Instant) {
+		self.enqueued.extend((0..n).map(|_| now));
+	}
+
+	pub(crate) fn received(&mut self, now: Instant) {
+		if let Some(enqueued) = self.enqueued.pop_front() {
+			if self.waited.len() == SAMPLES {
+				self.waited.pop_front();
+			}
+			self.waited.push_back(now - enqueued);
+		}
+	}
+}
+
+/// Time in queue of recently received values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueTimes {
+	/// How many values the numbers are based on, at most `SAMPLES`.
+	pub samples: usize,
+	pub mean: Duration,
+	pub p50: Duration,
+	pub p99: Duration,
+	pub max: Duration,
+}
+
+/// Starts timestamping the values of the channel `px` belongs to. Values
+/// already queued count as sent now.
+pub fn track<T: Send>(px: &Producer<T>) {
+	let mut state = px.shared.lock().unwrap();
+	let now = state.now();
+	let mut ages = Ages { enqueued: VecDeque::new(), waited: VecDeque::with_capacity(SAMPLES) };
+	ages.published(state.queue.len(), now);
+	state.ages = Some(ages);
+}
+
+/// How long the value at the front of the queue has been waiting. None if
+/// the queue is empty or the channel is not tracked.
+pub fn head_age<T: Send>(cx: &Consumer<T>) -> Option<Duration> {
+	let state = cx.shared.lock().unwrap();
+	let enqueued = *state.ages.as_ref()?.enqueued.front()?;
+	Some(state.now() - enqueued)
+}
+
+/// None if nothing was received since `track()`.
+pub fn queue_times<T: Send>(cx: &Consumer<T>) -> Option<QueueTimes> {
+	let mut waited: Vec<Duration> = {
+		let state = cx.shared.lock().unwrap();
+		state.ages.as_ref()?.waited.iter().cloned().collect()
+	};
+	if waited.is_empty() {
+		return None;
+	}
+	waited.sort();
+
+	let n = waited.len();
+	let percentile = |p: usize| waited[(n * p / 100).min(n - 1)];
+	Some(QueueTimes {
+		samples: n,
+		mean: waited.iter().sum::<Duration>() / n as u32,
+		p50: percentile(50),
+		p99: percentile(99),
+		max: waited[n - 1],
+	})
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::{channel, clock};
+
+	#[test]
+	fn head_age_and_queue_times_follow_the_clock() {
+		let (px, cx) = channel(0);
+		let clock = clock::MockClock::new();
+		clock::use_clock(&px, clock.clone());
+		px.send(0).unwrap();
+		track(&px);
+		assert_eq!(queue_times(&cx), None);
+
+		for i in 1..=3 {
+			clock.advance(Duration::from_millis(10));
+			px.send(i).unwrap();
+		}
+		assert_eq!(head_age(&cx), Some(Duration::from_millis(30)));
+
+		// received after 30, 20, 10 and 0 ms in the queue
+		for _ in 0..4 {
+			cx.recv().unwrap();
+		}
+		assert_eq!(head_age(&cx), None);
+		let times = queue_times(&cx).unwrap();
+		assert_eq!(times.samples, 4);
+		assert_eq!(times.mean, Duration::from_millis(15));
+		assert_eq!(times.p50, Duration::from_millis(20));
+		assert_eq!(times.max, Duration::from_millis(30));
+	}
+
+	#[test]
+	fn untracked_channels_report_nothing() {
+		let (px, cx) = channel(0);
+		px.send(1).unwrap();
+		assert_eq!(head_age(&cx), None);
+		assert_eq!(queue_times(&cx), None);
+	}
+}
@@ -0,0 +1,296 @@
+//! A multicast channel over a single shared ring. Every subscriber keeps its
+//! own read cursor into the ring, so a message is stored once no matter how
+//! many subscribers there are, instead of being copied into one queue per
+//! subscriber.
+//!
+//! In the default mode the publisher blocks once the ring is full until the
+//! slowest subscriber has moved past the oldest message. The lossy mode never
+//! blocks the publisher: it overwrites the oldest message instead, and a
+//! subscriber that got lapped learns how many messages it missed.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+
+use super::SendError;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvError {
+	/// The subscriber fell behind in lossy mode and this many messages were
+	/// overwritten before it could read them. The next recv() continues with
+	/// the oldest message still in the ring.
+	Lagged(u64),
+	/// The publisher is gone and every message has been read.
+	Disconnected,
+}
+
+struct State<T> {
+	ring: VecDeque<T>,
+	// sequence number of ring[0]
+	head: u64,
+	capacity: usize,
+	lossy: bool,
+	// next sequence number per subscriber, None once it was dropped
+	cursors: Vec<Option<u64>>,
+	publisher: bool,
+}
+
+impl<T> State<T> {
+	fn tail(&self) -> u64 {
+		self.head + self.ring.len() as u64
+	}
+
+	// Drops the messages every subscriber has already read.
+	fn trim(&mut self) {
+		let slowest = self.cursors.iter().filter_map(|c| *c).min().unwrap_or_else(|| self.tail());
+		while self.head < slowest && !self.ring.is_empty() {
+			self.ring.pop_front();
+			self.head += 1;
+		}
+	}
+
+	// Registers a cursor, reusing the slot of a dropped subscriber.
+	fn add_cursor(&mut self, cursor: u64) -> usize {
+		match self.cursors.iter().position(|c| c.is_none()) {
+			Some(id) => {
+				self.cursors[id] = Some(cursor);
+				id
+			}
+			None => {
+				self.cursors.push(Some(cursor));
+				self.cursors.len() - 1
+			}
+		}
+	}
+
+	fn subscribers(&self) -> usize {
+		self.cursors.iter().filter(|c| c.is_some()).count()
+	}
+}
+
+struct Shared<T> {
+	state: Mutex<State<T>>,
+	// signalled when a message is published or the publisher goes away
+	published: Condvar,
+	// signalled when a subscriber advanced or went away
+	consumed: Condvar,
+}
+
+pub struct Publisher<T: Send + Copy> {
+	shared: Arc<Shared<T>>,
+}
+
+pub struct Subscriber<T: Send + Copy> {
+	shared: Arc<Shared<T>>,
+	id: usize,
+}
+
+/// Creates a multicast channel whose ring holds `capacity` messages. The
+/// publisher blocks while the slowest subscriber is `capacity` messages
+/// behind.
+pub fn broadcast<T: Send + Copy>(capacity: usize) -> (Publisher<T>, Subscriber<T>) {
+	new(capacity, false)
+}
+
+/// Like `broadcast()`, but the publisher overwrites the oldest message
+/// instead of waiting for slow subscribers.
+pub fn broadcast_lossy<T: Send + Copy>(capacity: usize) -> (Publisher<T>, Subscriber<T>) {
+	new(capacity, true)
+}
+
+fn new<T: Send + Copy>(capacity: usize, lossy: bool) -> (Publisher<T>, Subscriber<T>) {
+	let capacity = capacity.max(1);
+	let shared = Arc::new(Shared {
+		state: Mutex::new(State {
+			ring: VecDeque::with_capacity(capacity),
+			head: 0,
+			capacity,
+			lossy,
+			cursors: vec![Some(0)],
+			publisher: true,
+		}),
+		published: Condvar::new(),
+		consumed: Condvar::new(),
+	});
+
+	(Publisher { shared: shared.clone() }, Subscriber { shared, id: 0 })
+}
+
+impl<T: Send + Copy> Publisher<T> {
+
+	/// Publishes `value` to every current subscriber. Fails if there are
+	/// none left.
+	pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+		let mut state = match self.shared.state.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Publisher::send() could not lock mutex."),
+		};
+
+		if state.lossy {
+			if state.ring.len() >= state.capacity {
+				state.ring.pop_front();
+				state.head += 1;
+			}
+		} else {
+			while state.ring.len() >= state.capacity && state.subscribers() > 0 {
+				state = self.shared.consumed.wait(state).unwrap();
+			}
+		}
+
+		if state.subscribers() == 0 {
+			return Err(SendError(value));
+		}
+
+		state.ring.push_back(value);
+		self.shared.published.notify_all();
+		Ok(())
+	}
+
+	/// Adds a subscriber that receives everything published from now on.
+	pub fn subscribe(&self) -> Subscriber<T> {
+		let mut state = self.shared.state.lock().unwrap();
+		let tail = state.tail();
+		let id = state.add_cursor(tail);
+		Subscriber { shared: self.shared.clone(), id }
+	}
+}
+
+impl<T: Send + Copy> Drop for Publisher<T> {
+	fn drop(&mut self) {
+		let mut state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+		state.publisher = false;
+		self.shared.published.notify_all();
+	}
+}
+
+impl<T: Send + Copy> Subscriber<T> {
+
+	/// Returns the next message this subscriber has not seen yet, blocking
+	/// until there is one.
+	pub fn recv(&self) -> Result<T, RecvError> {
+		let mut state = match self.shared.state.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Subscriber::recv() could not lock mutex."),
+		};
+
+		loop {
+			let cursor = state.cursors[self.id].unwrap();
+
+			if cursor < state.head {
+				let head = state.head;
+				state.cursors[self.id] = Some(head);
+				return Err(RecvError::Lagged(head - cursor));
+			}
+
+			if cursor < state.tail() {
+				let value = state.ring[(cursor - state.head) as usize];
+				state.cursors[self.id] = Some(cursor + 1);
+				state.trim();
+				self.shared.consumed.notify_all();
+				return Ok(value);
+			}
+
+			if !state.publisher {
+				return Err(RecvError::Disconnected);
+			}
+			state = self.shared.published.wait(state).unwrap();
+		}
+	}
+
+	/// Number of messages this subscriber has yet to read.
+	pub fn pending(&self) -> usize {
+		let state = self.shared.state.lock().unwrap();
+		let cursor = state.cursors[self.id].unwrap().max(state.head);
+		(state.tail() - cursor) as usize
+	}
+}
+
+impl<T: Send + Copy> Clone for Subscriber<T> {
+	/// The clone starts at the same position as the original.
+	fn clone(&self) -> Self {
+		let mut state = self.shared.state.lock().unwrap();
+		let cursor = state.cursors[self.id].unwrap();
+		let id = state.add_cursor(cursor);
+		Subscriber { shared: self.shared.clone(), id }
+	}
+}
+
+impl<T: Send + Copy> Drop for Subscriber<T> {
+	fn drop(&mut self) {
+		let mut state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+		state.cursors[self.id] = None;
+		state.trim();
+		self.shared.consumed.notify_all();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::thread;
+
+	#[test]
+	fn every_subscriber_sees_every_message() {
+		let (px, sub1) = broadcast(4);
+		let sub2 = px.subscribe();
+
+		let readers: Vec<_> = vec![sub1, sub2].into_iter().map(|sub| {
+			thread::spawn(move || {
+				let mut sum = 0;
+				while let Ok(v) = sub.recv() {
+					sum += v;
+				}
+				sum
+			})
+		}).collect();
+
+		for i in 0..100 {
+			px.send(i).unwrap();
+		}
+		drop(px);
+
+		for reader in readers {
+			assert_eq!(reader.join().unwrap(), 4950);
+		}
+	}
+
+	#[test]
+	fn ring_only_frees_slots_all_subscribers_passed() {
+		let (px, sub1) = broadcast(2);
+		let sub2 = sub1.clone();
+		px.send(1).unwrap();
+		px.send(2).unwrap();
+
+		assert_eq!(sub1.recv(), Ok(1));
+		assert_eq!(sub1.recv(), Ok(2));
+		assert_eq!(sub2.pending(), 2);
+
+		// sub2 still holds the ring full
+		let publisher = thread::spawn(move || {
+			px.send(3).unwrap();
+		});
+		assert_eq!(sub2.recv(), Ok(1));
+		publisher.join().unwrap();
+		assert_eq!(sub2.recv(), Ok(2));
+		assert_eq!(sub2.recv(), Ok(3));
+		assert_eq!(sub2.recv(), Err(RecvError::Disconnected));
+	}
+
+	#[test]
+	fn lossy_mode_laps_slow_subscribers() {
+		let (px, sub) = broadcast_lossy(3);
+		for i in 0..5 {
+			px.send(i).unwrap();
+		}
+		assert_eq!(sub.recv(), Err(RecvError::Lagged(2)));
+		assert_eq!(sub.recv(), Ok(2));
+		assert_eq!(sub.pending(), 2);
+	}
+
+	#[test]
+	fn send_fails_without_subscribers() {
+		let (px, sub) = broadcast(2);
+		drop(sub);
+		assert!(px.send(1).is_err());
+	}
+}
@@ -0,0 +1,301 @@
+//! `Producer::buffered()`, for producers that send many small values. The
+//! values collect in a buffer owned by the producer and go into the channel
+//! together, under a single lock, so the producers of a busy channel take
+//! its lock once per batch instead of once per value.
+//!
+//! The buffer is flushed when it is full, when `send()` finds that its
+//! oldest value has waited longer than the maximum delay, on `flush()`, and
+//! when the buffer is dropped. A producer that goes quiet is covered by a
+//! flusher thread, one per buffer, which moves the values out once the
+//! oldest is due, so no value waits much longer than the maximum delay. The
+//! flusher never waits for room: on a full bounded channel it moves what
+//! fits and tries again later.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{clock, Producer, SendError, Shared, State};
+
+/// A producer with a buffer of its own, see `Producer::buffered()`.
+///
+/// Dropping it flushes like `flush()`, so on a bounded channel the drop
+/// waits for room, which never comes while the consumer is alive but no
+/// longer receives. Call `into_inner()` first where that matters.
+pub struct Buffered<T: Send> {
+	// None once into_inner() took it
+	inner: Option<Producer<T>>,
+	buffer: Arc<Buffer<T>>,
+	capacity: usize,
+	max_delay: Duration,
+	clock: Option<Arc<dyn clock::Clock>>,
+	flusher: Option<thread::JoinHandle<()>>,
+}
+
+// The values, shared with the flusher thread.
+struct Buffer<T> {
+	pending: Mutex<Pending<T>>,
+	// signalled when the first value arrives and on stop
+	wake: Condvar,
+}
+
+struct Pending<T> {
+	values: Vec<T>,
+	// when the oldest buffered value was sent
+	oldest: Option<Instant>,
+	stopped: bool,
+}
+
+impl<T: Send + 'static> Producer<T> {
+	/// Puts a buffer for up to `capacity` values in front of the producer.
+	/// No value waits in it for much longer than `max_delay`, unless the
+	/// channel is full.
+	pub fn buffered(self, capacity: usize, max_delay: Duration) -> Buffered<T> {
+		let clock = self.shared.lock().unwrap().clock.clone();
+		let capacity = capacity.max(1);
+		let buffer = Arc::new(Buffer {
+			pending: Mutex::new(Pending { values: Vec::with_capacity(capacity), oldest: None, stopped: false }),
+			wake: Condvar::new(),
+		});
+		let flusher = {
+			let buffer = buffer.clone();
+			let shared = self.shared.clone();
+			let clock = clock.clone();
+			thread::spawn(move || run_flusher(&buffer, &shared, max_delay, &clock))
+		};
+		Buffered { inner: Some(self), buffer, capacity, max_delay, clock, flusher: Some(flusher) }
+	}
+}
+
+impl<T: Send> Buffered<T> {
+
+	fn pending(&self) -> MutexGuard<'_, Pending<T>> {
+		match self.buffer.pending.lock() {
+			Ok(pending) => pending,
+			Err(_) => panic!("Buffered::pending() could not lock mutex."),
+		}
+	}
+
+	/// Buffers `value` and flushes if the buffer is due. Fails with every
+	/// value not yet in the channel if there is no consumer left.
+	pub fn send(&mut self, value: T) -> Result<(), SendError<Vec<T>>> {
+		let now = clock::now(&self.clock);
+		let mut pending = self.pending();
+		pending.values.push(value);
+		let oldest = match pending.oldest {
+			Some(oldest) => oldest,
+			None => {
+				pending.oldest = Some(now);
+				self.buffer.wake.notify_one();
+				now
+			}
+		};
+		if pending.values.len() >= self.capacity || now - oldest >= self.max_delay {
+			self.flush_pending(&mut pending)?;
+		}
+		Ok(())
+	}
+
+	/// Moves the buffered values into the channel, waiting for room on a
+	/// bounded one. Fails like `send()`.
+	pub fn flush(&mut self) -> Result<(), SendError<Vec<T>>> {
+		let mut pending = self.pending();
+		self.flush_pending(&mut pending)
+	}
+
+	fn flush_pending(&self, pending: &mut Pending<T>) -> Result<(), SendError<Vec<T>>> {
+		if pending.values.is_empty() {
+			return Ok(());
+		}
+
+		let inner = self.inner.as_ref().unwrap();
+		let mut state = match inner.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Buffered::flush() could not lock mutex."),
+		};
+
+		while !pending.values.is_empty() {
+			let mut room = pending.values.len();
+			if let Some(bound) = state.bound.map(|b| b.max(1)) {
+				state = inner.wait_for_room(state, bound);
+				room = room.min(bound.saturating_sub(state.occupied()));
+			}
+			if state.consumers == 0 || state.poisoned {
+				pending.oldest = None;
+				return Err(SendError(pending.values.drain(..).collect()));
+			}
+
+			put(&inner.shared, &mut state, &mut pending.values, room);
+		}
+		pending.oldest = None;
+		Ok(())
+	}
+
+	/// Number of values waiting in the buffer.
+	pub fn buffered(&self) -> usize {
+		self.pending().values.len()
+	}
+
+	/// Flushes and gives back the producer underneath.
+	pub fn into_inner(mut self) -> Result<Producer<T>, SendError<Vec<T>>> {
+		self.flush()?;
+		self.stop_flusher();
+		Ok(self.inner.take().unwrap())
+	}
+
+	fn stop_flusher(&mut self) {
+		if let Some(flusher) = self.flusher.take() {
+			self.pending().stopped = true;
+			self.buffer.wake.notify_one();
+			if flusher.join().is_err() {
+				panic!("Buffered::stop_flusher() flusher thread panicked.");
+			}
+		}
+	}
+}
+
+impl<T: Send> Drop for Buffered<T> {
+	fn drop(&mut self) {
+		if self.inner.is_some() {
+			// nobody is left to hand the values back to
+			let _ = self.flush();
+		}
+		self.stop_flusher();
+	}
+}
+
+// Moves the first `n` values into the channel.
+fn put<T>(shared: &Shared<T>, state: &mut State<T>, values: &mut Vec<T>, n: usize) {
+	state.queue.extend(values.drain(..n));
+	state.publish(n);
+	shared.not_empty.notify_all();
+}
+
+// Flushes the buffer once its oldest value is due, until stopped. Moves only
+// what fits, so it never blocks the producer for long.
+fn run_flusher<T: Send>(buffer: &Buffer<T>, shared: &Shared<T>, max_delay: Duration, clock: &Option<Arc<dyn clock::Clock>>) {
+	let mut pending = buffer.pending.lock().unwrap();
+	loop {
+		if pending.stopped {
+			return;
+		}
+		let oldest = match pending.oldest {
+			Some(oldest) => oldest,
+			None => {
+				pending = buffer.wake.wait(pending).unwrap();
+				continue;
+			}
+		};
+
+		let now = clock::now(clock);
+		let mut sleep = (oldest + max_delay).saturating_duration_since(now);
+		if sleep.is_zero() {
+			if let Ok(mut state) = shared.lock() {
+				let room = match state.bound.map(|b| b.max(1)) {
+					// producers waiting in send() go first
+					Some(_) if state.serving < state.tickets => 0,
+					Some(bound) => bound.saturating_sub(state.occupied()),
+					None => pending.values.len(),
+				};
+				if state.consumers == 0 || state.poisoned {
+					// send() reports them
+					return;
+				}
+				let n = room.min(pending.values.len());
+				put(shared, &mut state, &mut pending.values, n);
+			}
+			if pending.values.is_empty() {
+				pending.oldest = None;
+				continue;
+			}
+			// full, try again later
+			sleep = max_delay;
+		}
+		if clock.is_some() {
+			// nobody notifies us when a mock clock moves
+			sleep = sleep.min(clock::TICK);
+		}
+		pending = buffer.wake.wait_timeout(pending, sleep.max(clock::TICK)).unwrap().0;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::{channel, sync_channel, TryRecvError};
+	use std::thread;
+
+	#[test]
+	fn values_wait_until_the_buffer_is_full() {
+		let (px, cx) = channel(0);
+		let mut px = px.buffered(3, Duration::from_secs(60));
+		px.send(1).unwrap();
+		px.send(2).unwrap();
+		assert_eq!(cx.try_recv(), Err(TryRecvError::Empty));
+		px.send(3).unwrap();
+		assert_eq!(px.buffered(), 0);
+		assert_eq!(cx.recv_batch(10, Duration::from_secs(0)), Ok(vec![1, 2, 3]));
+
+		px.send(4).unwrap();
+		drop(px);
+		assert_eq!(cx.recv(), Ok(4));
+	}
+
+	#[test]
+	fn old_values_are_flushed_without_another_send() {
+		let (px, cx) = channel(0);
+		let clock = clock::MockClock::new();
+		clock::use_clock(&px, clock.clone());
+		let mut px = px.buffered(100, Duration::from_millis(10));
+
+		px.send(1).unwrap();
+		px.send(2).unwrap();
+		thread::sleep(Duration::from_millis(20));
+		assert_eq!(cx.try_recv(), Err(TryRecvError::Empty));
+		clock.advance(Duration::from_millis(10));
+		assert_eq!(cx.recv(), Ok(1));
+		assert_eq!(cx.recv(), Ok(2));
+		assert_eq!(px.buffered(), 0);
+	}
+
+	#[test]
+	fn the_flusher_does_not_wait_for_room() {
+		let (px, cx) = sync_channel(1);
+		let mut px = px.buffered(100, Duration::from_millis(1));
+
+		px.send(1).unwrap();
+		px.send(2).unwrap();
+		// 1 fills the channel, 2 stays in the buffer until there is room
+		assert_eq!(cx.recv(), Ok(1));
+		assert_eq!(cx.recv(), Ok(2));
+		assert_eq!(px.buffered(), 0);
+		assert_eq!(px.into_inner().unwrap().size().unwrap(), 0);
+	}
+
+	#[test]
+	fn flush_respects_the_bound() {
+		let (px, cx) = sync_channel(2);
+		let mut px = px.buffered(5, Duration::from_secs(60));
+		let producer_thread = thread::spawn(move || {
+			for i in 0..5 {
+				px.send(i).unwrap();
+			}
+			px.into_inner().unwrap()
+		});
+		let received: Vec<_> = (0..5).map(|_| cx.recv().unwrap()).collect();
+		assert_eq!(received, vec![0, 1, 2, 3, 4]);
+		drop(producer_thread.join().unwrap());
+		assert_eq!(cx.recv().ok(), None);
+	}
+
+	#[test]
+	fn values_come_back_without_a_consumer() {
+		let (px, cx) = channel(0);
+		let mut px = px.buffered(3, Duration::from_secs(60));
+		px.send(1).unwrap();
+		px.send(2).unwrap();
+		drop(cx);
+		assert_eq!(px.send(3).err().unwrap().0, vec![1, 2, 3]);
+	}
+}
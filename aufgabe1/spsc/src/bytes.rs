@@ -0,0 +1,131 @@
+//! The channel specialized to bytes, as an in-process pipe. The producer
+//! implements `io::Write` and the consumer `io::Read`, so the two ends can be
+//! handed to anything that speaks the standard I/O traits.
+//!
+//! Writes block while a bounded channel is full and reads block while it is
+//! empty. Once every producer is gone a read returns `Ok(0)` (end of file),
+//! once every consumer is gone a write fails with `BrokenPipe`.
+
+use std::io;
+
+use super::{Consumer, Producer};
+
+pub type ByteProducer = Producer<u8>;
+pub type ByteConsumer = Consumer<u8>;
+
+/// Creates a pipe buffering at most `bound` bytes.
+pub fn byte_channel(bound: usize) -> (ByteProducer, ByteConsumer) {
+	super::sync_channel(bound)
+}
+
+impl io::Write for Producer<u8> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => return Err(io::Error::other("Producer::write() could not lock mutex.")),
+		};
+
+		// a zero bound still lets one byte through at a time
+		if let Some(bound) = state.bound {
+			state = self.wait_for_room(state, bound.max(1));
+		}
+		if state.poisoned {
+			return Err(io::Error::other("Producer::write() channel is poisoned."));
+		}
+		if state.consumers == 0 {
+			return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+		}
+
+		let room = match state.bound {
+			None => buf.len(),
+			Some(bound) => bound.max(1) - state.occupied(),
+		};
+		let n = room.min(buf.len());
+		state.queue.extend(&buf[..n]);
+		state.publish(n);
+		self.shared.not_empty.notify_all();
+		Ok(n)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl io::Read for Consumer<u8> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => return Err(io::Error::other("Consumer::read() could not lock mutex.")),
+		};
+
+		while state.queue.is_empty() && !state.disconnected() && !state.poisoned {
+			state.waiting += 1;
+			state = self.shared.wait(&self.shared.not_empty, state).unwrap();
+			state.waiting -= 1;
+		}
+		if state.poisoned {
+			return Err(io::Error::other("Consumer::read() channel is poisoned."));
+		}
+
+		let n = buf.len().min(state.queue.len());
+		for dst in &mut buf[..n] {
+			*dst = state.pop().unwrap();
+		}
+		if n > 0 {
+			self.shared.not_full.notify_all();
+		}
+		Ok(n)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::io::{Read, Write};
+	use std::thread;
+
+	#[test]
+	fn copy_through_a_small_pipe() {
+		let (mut px, mut cx) = byte_channel(7);
+		let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+		let expected = data.clone();
+
+		let producer_thread = thread::spawn(move || {
+			px.write_all(&data).unwrap();
+		});
+
+		let mut received = Vec::new();
+		cx.read_to_end(&mut received).unwrap();
+		producer_thread.join().unwrap();
+		assert_eq!(received, expected);
+	}
+
+	#[test]
+	fn write_after_reader_is_gone_is_a_broken_pipe() {
+		let (mut px, cx) = byte_channel(4);
+		drop(cx);
+		let err = px.write(b"hello").unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+	}
+
+	#[test]
+	fn read_returns_what_is_there() {
+		let (mut px, mut cx) = byte_channel(16);
+		px.write_all(b"abc").unwrap();
+		let mut buf = [0u8; 8];
+		assert_eq!(cx.read(&mut buf).unwrap(), 3);
+		assert_eq!(&buf[..3], b"abc");
+		drop(px);
+		assert_eq!(cx.read(&mut buf).unwrap(), 0);
+	}
+}
@@ -0,0 +1,170 @@
+//! Interrupting a single receive. `Consumer::recv_cancellable()` blocks like
+//! `recv()`, but returns `RecvError::Cancelled` as soon as its `CancelToken`
+//! is cancelled, so a consumer thread can be pulled out of a long wait, say
+//! to be reconfigured, and go back to the same channel afterwards.
+//!
+//! Unlike a ShutdownToken (shutdown.rs) the token is not attached to the
+//! channel: it only affects the calls it is passed to, other consumers and
+//! the queued values are left alone. A cancelled token stays cancelled, the
+//! next wait needs a new one.
+//!
+//! There is no async version, the crate has no async support (2015 edition).
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::{Consumer, RecvError, Shared};
+
+// Wakes up one waiting consumer.
+type Waker = Box<dyn Fn() + Send>;
+
+struct Inner {
+	cancelled: AtomicBool,
+	next_id: AtomicUsize,
+	// one per recv_cancellable() call waiting on this token
+	wakers: Mutex<Vec<(usize, Waker)>>,
+}
+
+#[derive(Clone)]
+pub struct CancelToken {
+	inner: Arc<Inner>,
+}
+
+impl CancelToken {
+	pub fn new() -> Self {
+		CancelToken {
+			inner: Arc::new(Inner { cancelled: AtomicBool::new(false), next_id: AtomicUsize::new(0), wakers: Mutex::new(Vec::new()) }),
+		}
+	}
+
+	/// Makes every `recv_cancellable()` call with this token return. Cancelling
+	/// twice does nothing.
+	pub fn cancel(&self) {
+		if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+			return;
+		}
+		for (_, wake) in self.inner.wakers.lock().unwrap().iter() {
+			wake();
+		}
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.inner.cancelled.load(Ordering::SeqCst)
+	}
+
+	// Registers a wakeup for the channel behind `shared` for as long as the
+	// returned guard lives.
+	fn register<T: Send + 'static>(&self, shared: &Arc<Shared<T>>) -> Registration<'_> {
+		let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+		let shared = Arc::downgrade(shared);
+		let wake = move || {
+			if let Some(shared) = shared.upgrade() {
+				// taking the lock makes sure a consumer that has not seen the
+				// token yet is already waiting and gets the notification
+				drop(shared.lock());
+				shared.not_empty.notify_all();
+			}
+		};
+		self.inner.wakers.lock().unwrap().push((id, Box::new(wake)));
+		Registration { token: self, id }
+	}
+}
+
+impl Default for CancelToken {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+struct Registration<'a> {
+	token: &'a CancelToken,
+	id: usize,
+}
+
+impl<'a> Drop for Registration<'a> {
+	fn drop(&mut self) {
+		self.token.inner.wakers.lock().unwrap().retain(|&(id, _)| id != self.id);
+	}
+}
+
+impl<T: Send + 'static> Consumer<T> {
+	/// Like `recv()`, but fails with `Cancelled` once `token` is cancelled,
+	/// even if values are queued.
+	pub fn recv_cancellable(&self, token: &CancelToken) -> Result<T, RecvError> {
+		// registered before locking the channel, cancel() takes the locks the
+		// other way round
+		let _registration = token.register(&self.shared);
+
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Consumer::recv_cancellable() could not lock mutex."),
+		};
+
+		loop {
+			if token.is_cancelled() {
+				return Err(RecvError::Cancelled);
+			}
+			if state.poisoned {
+				return Err(RecvError::Poisoned);
+			}
+			if let Some(value) = self.take(&mut state) {
+				return Ok(value);
+			}
+			if let Some(ref message) = state.panicked {
+				return Err(RecvError::ProducerPanicked(message.clone()));
+			}
+			if state.shut_down() {
+				return Err(RecvError::Shutdown);
+			}
+			if state.producers == 0 {
+				return Err(RecvError::Disconnected);
+			}
+			state.waiting += 1;
+			state = self.shared.wait(&self.shared.not_empty, state).unwrap();
+			state.waiting -= 1;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::channel;
+	use std::thread;
+	use std::time::Duration;
+
+	#[test]
+	fn cancel_interrupts_a_blocked_recv() {
+		let (px, cx) = channel::<u32>(0);
+		let token = CancelToken::new();
+
+		let consumer_thread = {
+			let token = token.clone();
+			thread::spawn(move || {
+				let first = cx.recv_cancellable(&token);
+				// the channel is still usable afterwards
+				(first, cx.recv())
+			})
+		};
+		thread::sleep(Duration::from_millis(10));
+		token.cancel();
+		thread::sleep(Duration::from_millis(10));
+		px.send(7).unwrap();
+
+		assert_eq!(consumer_thread.join().unwrap(), (Err(RecvError::Cancelled), Ok(7)));
+		assert!(token.inner.wakers.lock().unwrap().is_empty());
+	}
+
+	#[test]
+	fn a_cancelled_token_keeps_the_values_queued() {
+		let (px, cx) = channel(0);
+		px.send(1).unwrap();
+		let token = CancelToken::new();
+		assert_eq!(cx.recv_cancellable(&token), Ok(1));
+		px.send(2).unwrap();
+		token.cancel();
+		assert_eq!(cx.recv_cancellable(&token), Err(RecvError::Cancelled));
+		assert_eq!(cx.recv(), Ok(2));
+	}
+}
@@ -0,0 +1,170 @@
+//! Fault injection for tests, behind the `chaos` feature. Meant to be enabled
+//! from `[dev-dependencies]` only:
+//!
+//! ```toml
+//! spsc = { path = "...", features = ["chaos"] }
+//! ```
+//!
+//! Once `inject()` was called on a channel, its internal decision points roll
+//! the dice with the configured probabilities:
+//!
+//! - every send/recv may first sleep for up to `max_delay`,
+//! - every wait on a condvar may return early like a spurious wakeup would,
+//! - `try_send()` and `write_chunk()` may report a full channel,
+//! - `try_recv()` and `read_chunk()` may report an empty channel.
+//!
+//! None of these break the channel's guarantees, but they make the rare
+//! interleavings and results that code on top of it has to handle common.
+//! The dice are a seeded xorshift generator, so a failing run can be repeated
+//! as long as the threads behave the same.
+
+use std::time::Duration;
+
+use super::Producer;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+	/// Probability that an operation sleeps before it starts.
+	pub delay: f64,
+	pub max_delay: Duration,
+	/// Probability that a condvar wait wakes up without a notification.
+	pub spurious_wakeup: f64,
+	/// Probability that `try_send()`/`write_chunk()` claim the channel is full.
+	pub force_full: f64,
+	/// Probability that `try_recv()`/`read_chunk()` claim the channel is empty.
+	pub force_empty: f64,
+	pub seed: u64,
+}
+
+impl Default for Config {
+	/// Everything off, so a test only needs to set what it is after.
+	fn default() -> Self {
+		Config {
+			delay: 0.0,
+			max_delay: Duration::from_millis(1),
+			spurious_wakeup: 0.0,
+			force_full: 0.0,
+			force_empty: 0.0,
+			seed: 0x2545_f491_4f6c_dd1d,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+	Delay,
+	SpuriousWakeup,
+	Full,
+	Empty,
+}
+
+pub(crate) struct Chaos {
+	config: Config,
+	rng: u64,
+	injected: u64,
+}
+
+impl Chaos {
+	fn random(&mut self) -> f64 {
+		self.rng ^= self.rng << 13;
+		self.rng ^= self.rng >> 7;
+		self.rng ^= self.rng << 17;
+		(self.rng >> 11) as f64 / (1u64 << 53) as f64
+	}
+
+	/// Whether to inject `fault` at this point.
+	pub(crate) fn roll(&mut self, fault: Fault) -> bool {
+		let p = match fault {
+			Fault::Delay => self.config.delay,
+			Fault::SpuriousWakeup => self.config.spurious_wakeup,
+			Fault::Full => self.config.force_full,
+			Fault::Empty => self.config.force_empty,
+		};
+		let hit = p > 0.0 && self.random() < p;
+		if hit {
+			self.injected += 1;
+		}
+		hit
+	}
+
+	pub(crate) fn delay(&mut self) -> Option<Duration> {
+		if !self.roll(Fault::Delay) {
+			return None;
+		}
+		Some(self.config.max_delay.mul_f64(self.random()))
+	}
+}
+
+/// Turns on fault injection for the channel `px` belongs to.
+pub fn inject<T: Send>(px: &Producer<T>, config: Config) {
+	let rng = config.seed.max(1);
+	px.shared.lock().unwrap().chaos = Some(Chaos { config, rng, injected: 0 });
+}
+
+/// How many faults were injected into the channel so far.
+pub fn injected<T: Send>(px: &Producer<T>) -> u64 {
+	px.shared.lock().unwrap().chaos.as_ref().map_or(0, |c| c.injected)
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::{channel, sync_channel, TryRecvError, TrySendError};
+	use std::thread;
+
+	#[test]
+	fn forced_results_show_up_and_nothing_is_lost() {
+		let (px, cx) = channel::<u32>(16);
+		inject(&px, Config { force_full: 0.3, force_empty: 0.3, ..Config::default() });
+
+		let mut full = 0;
+		for i in 0..200 {
+			loop {
+				match px.try_send(i) {
+					Ok(()) => break,
+					Err(TrySendError::Full(_)) => full += 1,
+					Err(e) => panic!("{:?}", e),
+				}
+			}
+		}
+
+		let mut empty = 0;
+		let mut received = Vec::new();
+		while received.len() < 200 {
+			match cx.try_recv() {
+				Ok(v) => received.push(v),
+				Err(TryRecvError::Empty) => empty += 1,
+				Err(e) => panic!("{:?}", e),
+			}
+		}
+
+		assert!(full > 0 && empty > 0);
+		assert_eq!(received, (0..200).collect::<Vec<_>>());
+		assert_eq!(injected(&px), full + empty);
+	}
+
+	#[test]
+	fn delays_and_spurious_wakeups_keep_fifo_order() {
+		let (px, cx) = sync_channel::<u32>(2);
+		inject(&px, Config {
+			delay: 0.2,
+			max_delay: Duration::from_micros(200),
+			spurious_wakeup: 0.5,
+			..Config::default()
+		});
+
+		let producer_thread = thread::spawn(move || {
+			for i in 0..300 {
+				px.send(i).unwrap();
+			}
+			px
+		});
+
+		for i in 0..300 {
+			assert_eq!(cx.recv().unwrap(), i);
+		}
+		let px = producer_thread.join().unwrap();
+		assert!(injected(&px) > 0);
+	}
+}
@@ -0,0 +1,104 @@
+//! Pluggable time source. By default a channel measures timeouts with
+//! `Instant::now()`. A test can give it a MockClock instead and move time
+//! forward by hand, so a `recv_timeout(Duration::from_secs(30))` test takes
+//! as long as it takes to call `advance()` rather than 30 seconds, and
+//! whether it timed out depends only on how far the test advanced the clock.
+//!
+//! While a channel waits against a clock other than the real one it wakes up
+//! every `TICK` to look at the clock again, since nobody notifies it when
+//! the clock is advanced.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::Producer;
+
+pub const TICK: Duration = Duration::from_millis(1);
+
+pub trait Clock: Send + Sync {
+	fn now(&self) -> Instant;
+}
+
+/// A clock that only moves when `advance()` is called.
+pub struct MockClock {
+	start: Instant,
+	elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+	pub fn new() -> Arc<Self> {
+		Arc::new(MockClock { start: Instant::now(), elapsed: Mutex::new(Duration::from_secs(0)) })
+	}
+
+	pub fn advance(&self, by: Duration) {
+		*self.elapsed.lock().unwrap() += by;
+	}
+
+	/// How far the clock was advanced in total.
+	pub fn elapsed(&self) -> Duration {
+		*self.elapsed.lock().unwrap()
+	}
+}
+
+impl Clock for MockClock {
+	fn now(&self) -> Instant {
+		self.start + self.elapsed()
+	}
+}
+
+// The time according to `clock`, real time for None.
+pub(crate) fn now(clock: &Option<Arc<dyn Clock>>) -> Instant {
+	clock.as_ref().map_or_else(Instant::now, |c| c.now())
+}
+
+/// Makes the channel `px` belongs to measure its timeouts with `clock`.
+pub fn use_clock<T: Send>(px: &Producer<T>, clock: Arc<dyn Clock>) {
+	px.shared.lock().unwrap().clock = Some(clock);
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::{channel, RecvTimeoutError};
+	use std::thread;
+
+	#[test]
+	fn timeout_follows_the_mock_clock() {
+		let clock = MockClock::new();
+		let (px, cx) = channel::<u32>(4);
+		use_clock(&px, clock.clone());
+
+		let real = Instant::now();
+		let consumer_thread = thread::spawn(move || cx.recv_timeout(Duration::from_secs(3600)));
+
+		// the consumer may read the clock before or after the first advance,
+		// so keep going in steps until it gives up
+		while !consumer_thread.is_finished() {
+			clock.advance(Duration::from_secs(600));
+			thread::sleep(Duration::from_millis(2));
+		}
+
+		assert_eq!(consumer_thread.join().unwrap(), Err(RecvTimeoutError::Timeout));
+		assert!(clock.elapsed() >= Duration::from_secs(3600));
+		assert!(real.elapsed() < Duration::from_secs(60));
+		drop(px);
+	}
+
+	#[test]
+	fn value_arrives_before_virtual_deadline() {
+		let clock = MockClock::new();
+		let (px, cx) = channel::<u32>(4);
+		use_clock(&px, clock.clone());
+
+		let consumer_thread = thread::spawn(move || cx.recv_timeout(Duration::from_secs(10)));
+
+		// no matter how long this takes in real time, the deadline is not hit
+		thread::sleep(Duration::from_millis(20));
+		clock.advance(Duration::from_secs(9));
+		px.send(7).unwrap();
+
+		assert_eq!(consumer_thread.join().unwrap(), Ok(7));
+		assert_eq!(clock.elapsed(), Duration::from_secs(9));
+	}
+}
@@ -0,0 +1,209 @@
+//! A shim with the same types, method names and error types as
+//! `std::sync::mpsc`, backed by this crate's channel. Switching over is meant
+//! to be a matter of changing
+//!
+//!     use std::sync::mpsc::{channel, Sender, Receiver};
+//!
+//! into
+//!
+//!     use spsc::compat::{channel, Sender, Receiver};
+//!
+//! std has no poisoned channels, a poisoned one is reported as disconnected.
+
+use std::time::Duration;
+
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+
+use super::{Consumer, Producer};
+use super::{RecvTimeoutError as SpscRecvTimeoutError, TryRecvError as SpscTryRecvError,
+	TrySendError as SpscTrySendError};
+
+/// The sending half of `channel()`. Never blocks.
+pub struct Sender<T: Send> {
+	inner: Producer<T>,
+}
+
+/// The sending half of `sync_channel()`. Blocks while the channel is full.
+pub struct SyncSender<T: Send> {
+	inner: Producer<T>,
+}
+
+/// The receiving half of either kind of channel.
+pub struct Receiver<T: Send> {
+	inner: Consumer<T>,
+}
+
+pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+	let (px, cx) = super::channel(0);
+	(Sender { inner: px }, Receiver { inner: cx })
+}
+
+pub fn sync_channel<T: Send>(bound: usize) -> (SyncSender<T>, Receiver<T>) {
+	let (px, cx) = super::sync_channel(bound);
+	(SyncSender { inner: px }, Receiver { inner: cx })
+}
+
+impl<T: Send> Sender<T> {
+	pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+		self.inner.send(t).map_err(|e| SendError(e.0))
+	}
+}
+
+impl<T: Send> Clone for Sender<T> {
+	fn clone(&self) -> Self {
+		Sender { inner: self.inner.clone() }
+	}
+}
+
+impl<T: Send> SyncSender<T> {
+	pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+		self.inner.send(t).map_err(|e| SendError(e.0))
+	}
+
+	pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+		self.inner.try_send(t).map_err(|e| match e {
+			SpscTrySendError::Full(t) => TrySendError::Full(t),
+			SpscTrySendError::Disconnected(t) | SpscTrySendError::Poisoned(t) => TrySendError::Disconnected(t),
+		})
+	}
+}
+
+impl<T: Send> Clone for SyncSender<T> {
+	fn clone(&self) -> Self {
+		SyncSender { inner: self.inner.clone() }
+	}
+}
+
+impl<T: Send> Receiver<T> {
+	pub fn recv(&self) -> Result<T, RecvError> {
+		self.inner.recv().map_err(|_| RecvError)
+	}
+
+	pub fn try_recv(&self) -> Result<T, TryRecvError> {
+		self.inner.try_recv().map_err(|e| match e {
+			SpscTryRecvError::Empty => TryRecvError::Empty,
+			SpscTryRecvError::Disconnected | SpscTryRecvError::Poisoned => TryRecvError::Disconnected,
+		})
+	}
+
+	pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+		self.inner.recv_timeout(timeout).map_err(|e| match e {
+			SpscRecvTimeoutError::Timeout => RecvTimeoutError::Timeout,
+			SpscRecvTimeoutError::Disconnected | SpscRecvTimeoutError::Poisoned => RecvTimeoutError::Disconnected,
+		})
+	}
+
+	pub fn iter(&self) -> Iter<'_, T> {
+		Iter { rx: self }
+	}
+
+	pub fn try_iter(&self) -> TryIter<'_, T> {
+		TryIter { rx: self }
+	}
+}
+
+/// Blocks on every `next()` and ends once all senders are gone.
+pub struct Iter<'a, T: Send + 'a> {
+	rx: &'a Receiver<T>,
+}
+
+/// Yields what is queued right now without blocking.
+pub struct TryIter<'a, T: Send + 'a> {
+	rx: &'a Receiver<T>,
+}
+
+/// Owning version of `Iter`.
+pub struct IntoIter<T: Send> {
+	rx: Receiver<T>,
+}
+
+impl<'a, T: Send> Iterator for Iter<'a, T> {
+	type Item = T;
+
+	fn next(&mut self) -> Option<T> {
+		self.rx.recv().ok()
+	}
+}
+
+impl<'a, T: Send> Iterator for TryIter<'a, T> {
+	type Item = T;
+
+	fn next(&mut self) -> Option<T> {
+		self.rx.try_recv().ok()
+	}
+}
+
+impl<T: Send> Iterator for IntoIter<T> {
+	type Item = T;
+
+	fn next(&mut self) -> Option<T> {
+		self.rx.recv().ok()
+	}
+}
+
+impl<'a, T: Send> IntoIterator for &'a Receiver<T> {
+	type Item = T;
+	type IntoIter = Iter<'a, T>;
+
+	fn into_iter(self) -> Iter<'a, T> {
+		self.iter()
+	}
+}
+
+impl<T: Send> IntoIterator for Receiver<T> {
+	type Item = T;
+	type IntoIter = IntoIter<T>;
+
+	fn into_iter(self) -> IntoIter<T> {
+		IntoIter { rx: self }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::thread;
+
+	#[test]
+	fn iter_ends_when_senders_are_dropped() {
+		let (tx, rx) = channel();
+		let tx2 = tx.clone();
+
+		let producer_thread = thread::spawn(move || {
+			for i in 0..10 {
+				tx.send(i).unwrap();
+			}
+		});
+		tx2.send(10).unwrap();
+		drop(tx2);
+		producer_thread.join().unwrap();
+
+		let sum: i32 = rx.iter().sum();
+		assert_eq!(sum, 55);
+		assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+	}
+
+	#[test]
+	fn sync_sender_reports_full_and_disconnected() {
+		let (tx, rx) = sync_channel(1);
+		tx.try_send(1).unwrap();
+		assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+		assert_eq!(rx.recv(), Ok(1));
+		drop(rx);
+		assert_eq!(tx.try_send(3), Err(TrySendError::Disconnected(3)));
+		assert_eq!(tx.send(4), Err(SendError(4)));
+	}
+
+	#[test]
+	fn recv_timeout_and_try_iter() {
+		let (tx, rx) = channel::<u8>();
+		assert_eq!(rx.recv_timeout(Duration::from_millis(5)), Err(RecvTimeoutError::Timeout));
+		tx.send(1).unwrap();
+		tx.send(2).unwrap();
+		assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![1, 2]);
+		drop(tx);
+		assert_eq!(rx.recv_timeout(Duration::from_millis(5)), Err(RecvTimeoutError::Disconnected));
+		assert_eq!(rx.into_iter().next(), None);
+	}
+}
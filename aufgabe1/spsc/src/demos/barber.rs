@@ -0,0 +1,130 @@
+//! The sleeping barber. The waiting room is a bounded channel with one slot
+//! per chair: a customer who arrives takes a seat with `try_send()` and
+//! leaves if that reports the room as full. The barber sleeps in `recv()`
+//! while the room is empty. Once the haircut is done the barber releases the
+//! customer's Semaphore, which the customer has been waiting on.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::super::semaphore::Semaphore;
+use super::super::{sync_channel, Error, TrySendError};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+	pub customers: usize,
+	pub chairs: usize,
+	pub barbers: usize,
+	/// Mean time between two arrivals. The actual gaps are spread evenly
+	/// between zero and twice this.
+	pub arrival_interval: Duration,
+	pub haircut: Duration,
+	pub seed: u64,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			customers: 50,
+			chairs: 3,
+			barbers: 1,
+			arrival_interval: Duration::from_millis(2),
+			haircut: Duration::from_millis(3),
+			seed: 42,
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct Report {
+	pub served: usize,
+	pub turned_away: usize,
+	pub elapsed: Duration,
+}
+
+// xorshift64, good enough to spread the arrivals
+fn next_random(state: &mut u64) -> u64 {
+	*state ^= *state << 13;
+	*state ^= *state >> 7;
+	*state ^= *state << 17;
+	*state
+}
+
+/// Fails without starting anybody if there is no barber.
+pub fn run(config: &Config) -> Result<Report, Error> {
+	if config.barbers == 0 {
+		return Err(Error { message: "barber::run() needs at least 1 barber, got 0.".to_string() });
+	}
+
+	let (waiting_room, queue) = sync_channel::<usize>(config.chairs);
+	let done: Arc<Vec<Semaphore>> = Arc::new((0..config.customers).map(|_| Semaphore::new(0)).collect());
+	let start = Instant::now();
+
+	let barbers: Vec<_> = (0..config.barbers).map(|_| {
+		let (queue, done) = (queue.clone(), done.clone());
+		let haircut = config.haircut;
+		thread::spawn(move || {
+			let mut served = 0;
+			// asleep in recv() while nobody is waiting
+			while let Ok(customer) = queue.recv() {
+				thread::sleep(haircut);
+				done[customer].release();
+				served += 1;
+			}
+			served
+		})
+	}).collect();
+	drop(queue);
+
+	let mut random = config.seed.max(1);
+	let max_gap = config.arrival_interval.as_micros() as u64 * 2 + 1;
+	let customers: Vec<_> = (0..config.customers).map(|id| {
+		thread::sleep(Duration::from_micros(next_random(&mut random) % max_gap));
+
+		let (waiting_room, done) = (waiting_room.clone(), done.clone());
+		thread::spawn(move || {
+			match waiting_room.try_send(id) {
+				Ok(()) => {
+					done[id].acquire();
+					true
+				}
+				Err(TrySendError::Full(_)) => false,
+				Err(TrySendError::Disconnected(_)) | Err(TrySendError::Poisoned(_)) => panic!("the barber shop closed early"),
+			}
+		})
+	}).collect();
+	drop(waiting_room);
+
+	let turned_away = customers.into_iter().map(|c| c.join().unwrap()).filter(|&seated| !seated).count();
+	let served = barbers.into_iter().map(|b| b.join().unwrap()).sum();
+
+	Ok(Report { served, turned_away, elapsed: start.elapsed() })
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn everybody_is_served_or_turned_away() {
+		let report = run(&Config::default()).unwrap();
+		assert_eq!(report.served + report.turned_away, 50);
+		assert!(report.served > 0);
+	}
+
+	#[test]
+	fn enough_chairs_means_nobody_leaves() {
+		let config = Config { customers: 20, chairs: 20, ..Config::default() };
+		let report = run(&config).unwrap();
+		assert_eq!(report.served, 20);
+		assert_eq!(report.turned_away, 0);
+	}
+
+	#[test]
+	fn no_barber_is_rejected() {
+		let config = Config { barbers: 0, ..Config::default() };
+		assert!(run(&config).is_err());
+	}
+}
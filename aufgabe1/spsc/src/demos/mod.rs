@@ -0,0 +1,8 @@
+//! The classic synchronization problems from the lecture, solved with the
+//! primitives of this crate. Every demo has a `run()` that returns a report
+//! instead of printing, so the tests can check it; the programs in
+//! `examples/` print them.
+
+pub mod barber;
+pub mod philosophers;
+pub mod readers_writers;
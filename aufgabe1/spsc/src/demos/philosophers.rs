@@ -0,0 +1,157 @@
+//! Dining philosophers. Every fork is a binary Semaphore, and the modes
+//! differ only in how a philosopher picks up the two forks next to them.
+//!
+//! In the naive mode everybody takes the left fork first. If all of them get
+//! hungry at once, each holds one fork and waits for the other one forever.
+//! Since a demo that hangs is not much fun, a philosopher gives up after
+//! waiting `patience` for their second fork and the run is reported as
+//! deadlocked.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::super::semaphore::Semaphore;
+use super::super::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+	/// Left fork first, then the right one. Deadlock-prone.
+	Naive,
+	/// Lower numbered fork first. Breaks the circular wait.
+	Ordered,
+	/// Left fork first, but only n - 1 philosophers may sit at the table at
+	/// the same time (a counting semaphore), so one of them always gets both.
+	Butler,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+	pub philosophers: usize,
+	pub meals: usize,
+	pub mode: Mode,
+	pub think: Duration,
+	pub eat: Duration,
+	/// Pause between taking the first and the second fork. The larger, the
+	/// more likely the naive mode deadlocks.
+	pub hesitate: Duration,
+	/// How long to wait for the second fork before declaring a deadlock.
+	pub patience: Duration,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			philosophers: 5,
+			meals: 10,
+			mode: Mode::Ordered,
+			think: Duration::from_millis(1),
+			eat: Duration::from_millis(1),
+			hesitate: Duration::from_millis(1),
+			patience: Duration::from_secs(1),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct Report {
+	pub meals_eaten: Vec<usize>,
+	pub deadlocked: bool,
+	pub elapsed: Duration,
+}
+
+/// Fails without starting anybody if there are fewer than two philosophers,
+/// a single one would need the one fork on the table twice.
+pub fn run(config: &Config) -> Result<Report, Error> {
+	let n = config.philosophers;
+	if n < 2 {
+		return Err(Error { message: format!("philosophers::run() needs at least 2 philosophers, got {}.", n) });
+	}
+	let forks: Arc<Vec<Semaphore>> = Arc::new((0..n).map(|_| Semaphore::new(1)).collect());
+	let table = Arc::new(Semaphore::new(n.saturating_sub(1).max(1)));
+	let deadlocked = Arc::new(AtomicBool::new(false));
+	let start = Instant::now();
+
+	let philosophers: Vec<_> = (0..n).map(|i| {
+		let (forks, table, deadlocked) = (forks.clone(), table.clone(), deadlocked.clone());
+		let config = config.clone();
+
+		thread::spawn(move || {
+			let left = i;
+			let right = (i + 1) % n;
+			let (first, second) = match config.mode {
+				Mode::Ordered => (left.min(right), left.max(right)),
+				Mode::Naive | Mode::Butler => (left, right),
+			};
+
+			let mut meals = 0;
+			while meals < config.meals && !deadlocked.load(Ordering::SeqCst) {
+				thread::sleep(config.think);
+
+				let _seat = if config.mode == Mode::Butler { Some(table.access()) } else { None };
+
+				forks[first].acquire();
+				thread::sleep(config.hesitate);
+				if !forks[second].acquire_timeout(config.patience) {
+					forks[first].release();
+					deadlocked.store(true, Ordering::SeqCst);
+					break;
+				}
+
+				thread::sleep(config.eat);
+				meals += 1;
+
+				forks[second].release();
+				forks[first].release();
+			}
+			meals
+		})
+	}).collect();
+
+	let meals_eaten = philosophers.into_iter().map(|p| p.join().unwrap()).collect();
+
+	Ok(Report {
+		meals_eaten,
+		deadlocked: deadlocked.load(Ordering::SeqCst),
+		elapsed: start.elapsed(),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn ordered_and_butler_modes_finish() {
+		for &mode in &[Mode::Ordered, Mode::Butler] {
+			let config = Config { mode, meals: 5, ..Config::default() };
+			let report = run(&config).unwrap();
+			assert!(!report.deadlocked, "{:?} deadlocked", mode);
+			assert_eq!(report.meals_eaten, vec![5; 5]);
+		}
+	}
+
+	#[test]
+	fn a_single_philosopher_is_rejected() {
+		for philosophers in 0..2 {
+			let config = Config { philosophers, ..Config::default() };
+			assert!(run(&config).is_err());
+		}
+	}
+
+	#[test]
+	fn naive_mode_deadlocks_when_everybody_hesitates() {
+		let config = Config {
+			mode: Mode::Naive,
+			think: Duration::from_millis(0),
+			hesitate: Duration::from_millis(50),
+			patience: Duration::from_millis(200),
+			..Config::default()
+		};
+		let report = run(&config).unwrap();
+		assert!(report.deadlocked);
+		assert!(report.meals_eaten.iter().all(|&m| m < config.meals));
+	}
+}
@@ -0,0 +1,143 @@
+//! Readers and writers hammering one RwLock for a fixed amount of time. The
+//! report counts the operations of each side and the longest time a thread
+//! had to wait for the lock, which is where starvation shows up: with
+//! `ReaderPreference` and enough readers the writers barely get in, with
+//! `WriterPreference` it is the other way around, and `Fair` keeps both
+//! waits short at some cost in throughput.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::super::rwlock::{Policy, RwLock};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+	pub readers: usize,
+	pub writers: usize,
+	pub policy: Policy,
+	pub duration: Duration,
+	/// How long a reader holds the lock.
+	pub read_time: Duration,
+	/// How long a writer holds the lock.
+	pub write_time: Duration,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			readers: 8,
+			writers: 2,
+			policy: Policy::Fair,
+			duration: Duration::from_millis(500),
+			read_time: Duration::from_micros(200),
+			write_time: Duration::from_micros(200),
+		}
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+	pub reads: u64,
+	pub writes: u64,
+	pub max_read_wait: Duration,
+	pub max_write_wait: Duration,
+	pub elapsed: Duration,
+}
+
+impl Report {
+	pub fn reads_per_sec(&self) -> f64 {
+		self.reads as f64 / self.elapsed.as_secs_f64()
+	}
+
+	pub fn writes_per_sec(&self) -> f64 {
+		self.writes as f64 / self.elapsed.as_secs_f64()
+	}
+}
+
+pub fn run(config: &Config) -> Report {
+	let lock = Arc::new(RwLock::new(0u64, config.policy));
+	let stop = Arc::new(AtomicBool::new(false));
+	let start = Instant::now();
+
+	let spawn = |writer: bool| {
+		let (lock, stop) = (lock.clone(), stop.clone());
+		let hold = if writer { config.write_time } else { config.read_time };
+
+		thread::spawn(move || {
+			let mut ops = 0u64;
+			let mut max_wait = Duration::from_secs(0);
+			while !stop.load(Ordering::Relaxed) {
+				let asked = Instant::now();
+				if writer {
+					let mut value = lock.write();
+					max_wait = max_wait.max(asked.elapsed());
+					*value += 1;
+					thread::sleep(hold);
+				} else {
+					let _value = lock.read();
+					max_wait = max_wait.max(asked.elapsed());
+					thread::sleep(hold);
+				}
+				ops += 1;
+			}
+			(writer, ops, max_wait)
+		})
+	};
+
+	let threads: Vec<_> = (0..config.readers).map(|_| spawn(false))
+		.chain((0..config.writers).map(|_| spawn(true)))
+		.collect();
+
+	thread::sleep(config.duration);
+	stop.store(true, Ordering::Relaxed);
+
+	let mut report = Report::default();
+	for t in threads {
+		let (writer, ops, max_wait) = t.join().unwrap();
+		if writer {
+			report.writes += ops;
+			report.max_write_wait = report.max_write_wait.max(max_wait);
+		} else {
+			report.reads += ops;
+			report.max_read_wait = report.max_read_wait.max(max_wait);
+		}
+	}
+	report.elapsed = start.elapsed();
+	report
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	fn short_run(policy: Policy) -> Report {
+		run(&Config {
+			readers: 2,
+			writers: 2,
+			policy,
+			duration: Duration::from_millis(100),
+			read_time: Duration::from_micros(50),
+			write_time: Duration::from_micros(50),
+		})
+	}
+
+	#[test]
+	fn every_policy_makes_progress() {
+		// the preference policies may well starve one side completely
+		for &policy in &[Policy::ReaderPreference, Policy::WriterPreference] {
+			let report = short_run(policy);
+			assert!(report.reads + report.writes > 0, "{:?}: no progress", policy);
+		}
+	}
+
+	#[test]
+	fn fair_policy_serves_both_sides() {
+		let report = short_run(Policy::Fair);
+		assert!(report.reads > 0);
+		assert!(report.writes > 0);
+		assert!(report.reads_per_sec() > 0.0);
+	}
+}
@@ -0,0 +1,92 @@
+//! Two channels wired up as one connection. `duplex()` returns two ends, one
+//! sending `A` and receiving `B`, the other sending `B` and receiving `A`,
+//! so the ends of the two channels cannot get crossed.
+
+use std::time::Duration;
+
+use super::{sync_channel, Consumer, Producer, RecvError, RecvTimeoutError, SendError, TryRecvError};
+
+/// One end of a duplex channel: sends `S`, receives `R`.
+pub struct Duplex<S: Send, R: Send> {
+	px: Producer<S>,
+	cx: Consumer<R>,
+}
+
+/// Creates a connection where each direction holds up to `bound` messages.
+/// With a bound of zero `send()` waits for the other end to receive, so both
+/// ends calling `exchange()` at the same time would wait for each other.
+pub fn duplex<A: Send, B: Send>(bound: usize) -> (Duplex<A, B>, Duplex<B, A>) {
+	let (px_a, cx_a) = sync_channel(bound);
+	let (px_b, cx_b) = sync_channel(bound);
+	(Duplex { px: px_a, cx: cx_b }, Duplex { px: px_b, cx: cx_a })
+}
+
+impl<S: Send, R: Send> Duplex<S, R> {
+
+	/// Fails once the other end is gone.
+	pub fn send(&self, value: S) -> Result<(), SendError<S>> {
+		self.px.send(value)
+	}
+
+	/// Fails once the other end is gone and everything it sent was received.
+	pub fn recv(&self) -> Result<R, RecvError> {
+		self.cx.recv()
+	}
+
+	pub fn try_recv(&self) -> Result<R, TryRecvError> {
+		self.cx.try_recv()
+	}
+
+	pub fn recv_timeout(&self, timeout: Duration) -> Result<R, RecvTimeoutError> {
+		self.cx.recv_timeout(timeout)
+	}
+
+	/// Sends `value` and waits for the next message from the other end.
+	pub fn exchange(&self, value: S) -> Result<R, RecvError> {
+		self.send(value).map_err(|_| RecvError::Disconnected)?;
+		self.recv()
+	}
+
+	/// Splits the end into its two halves, to send and receive from
+	/// different threads.
+	pub fn split(self) -> (Producer<S>, Consumer<R>) {
+		(self.px, self.cx)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::thread;
+
+	#[test]
+	fn each_end_receives_what_the_other_sent() {
+		let (client, server) = duplex::<u32, String>(1);
+
+		let server_thread = thread::spawn(move || {
+			while let Ok(n) = server.recv() {
+				server.send(format!("#{}", n)).unwrap();
+			}
+		});
+
+		for i in 0..10 {
+			assert_eq!(client.exchange(i), Ok(format!("#{}", i)));
+		}
+		drop(client);
+		server_thread.join().unwrap();
+	}
+
+	#[test]
+	fn split_halves_stay_connected() {
+		let (left, right) = duplex::<u8, u8>(4);
+		let (px, cx) = left.split();
+		px.send(1).unwrap();
+		assert_eq!(right.recv(), Ok(1));
+		right.send(2).unwrap();
+		assert_eq!(cx.recv(), Ok(2));
+		drop(right);
+		assert!(px.send(3).is_err());
+		assert_eq!(cx.try_recv(), Err(TryRecvError::Disconnected));
+	}
+}
@@ -0,0 +1,355 @@
+//! A fixed pool of worker threads running tasks from a job queue (jobs.rs).
+//! `spawn()` returns a TaskHandle that delivers the task's result through a
+//! channel of its own with room for exactly one value.
+//!
+//! A task that panics does not take its worker down: the panic is caught and
+//! handed to whoever joins the task. Dropping the executor (or calling
+//! `shutdown()`) lets the workers finish every task already submitted and
+//! then joins them.
+//!
+//! `Executor::scaling()` starts a pool that grows and shrinks with the queue
+//! instead. A supervisor thread watches the queue (watermark.rs): as long as
+//! it stays at the high watermark, another worker is added every interval,
+//! and once it is back down to the low one, workers that found nothing to do
+//! for an interval retire. The pool stays within its minimum and maximum.
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::jobs::{self, JobQueue, Jobs};
+use super::watermark::Watermark;
+use super::{panic_message, sync_channel, Consumer, Error, RecvError, RecvTimeoutError, TryRecvError};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TaskError {
+	/// The task panicked, with this message.
+	Panicked(String),
+	/// The task was dropped without running.
+	Cancelled,
+}
+
+impl fmt::Display for TaskError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			TaskError::Panicked(ref message) => write!(f, "TaskHandle::join() task panicked: {}", message),
+			TaskError::Cancelled => write!(f, "TaskHandle::join() task was cancelled."),
+		}
+	}
+}
+
+pub struct Executor {
+	queue: Option<JobQueue>,
+	workers: Vec<thread::JoinHandle<usize>>,
+	// only for Executor::scaling(), the supervisor owns the workers then
+	supervisor: Option<(Arc<Pool>, thread::JoinHandle<Vec<usize>>)>,
+}
+
+/// How `Executor::scaling()` sizes its pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scaling {
+	/// Workers that never retire, at least 1.
+	pub min: usize,
+	pub max: usize,
+	/// Waiting tasks that count as too many.
+	pub high: usize,
+	/// Waiting tasks that count as few enough to retire workers, below
+	/// `high`.
+	pub low: usize,
+	/// How long the queue has to stay at `high` for each added worker, and
+	/// how long a worker has to be idle to retire.
+	pub interval: Duration,
+}
+
+// Shared by the supervisor and the workers of a scaling executor.
+struct Pool {
+	min: usize,
+	live: AtomicUsize,
+	// whether the last watermark event was High, no worker retires then
+	above: AtomicBool,
+	stopping: AtomicBool,
+}
+
+impl Pool {
+	// Takes an idle worker off the count if the pool may shrink.
+	fn retire(&self) -> bool {
+		if self.above.load(Ordering::SeqCst) {
+			return false;
+		}
+		let mut live = self.live.load(Ordering::SeqCst);
+		while live > self.min {
+			match self.live.compare_exchange(live, live - 1, Ordering::SeqCst, Ordering::SeqCst) {
+				Ok(_) => return true,
+				Err(current) => live = current,
+			}
+		}
+		false
+	}
+}
+
+/// The result of a task submitted with `Executor::spawn()`.
+pub struct TaskHandle<R: Send> {
+	result: Consumer<Result<R, TaskError>>,
+}
+
+impl Executor {
+	/// Starts `threads` workers. Fails if `threads` is zero, nothing would
+	/// ever run the tasks.
+	pub fn new(threads: usize) -> Result<Self, Error> {
+		if threads == 0 {
+			return Err(Error { message: "Executor::new() needs at least 1 thread, got 0.".to_string() });
+		}
+		let (queue, jobs) = jobs::job_queue();
+		let workers = (0..threads).map(|i| {
+			let jobs = jobs.clone();
+			thread::Builder::new()
+				.name(format!("executor-{}", i))
+				.spawn(move || jobs::work(&jobs).ran)
+				.unwrap()
+		}).collect();
+		Ok(Executor { queue: Some(queue), workers, supervisor: None })
+	}
+
+	/// Starts `scaling.min` workers, more are added and retired again as the
+	/// queue grows and shrinks, see the module documentation. Fails if
+	/// `scaling` does not describe a pool that can run tasks.
+	pub fn scaling(scaling: Scaling) -> Result<Self, Error> {
+		if scaling.min == 0 || scaling.max < scaling.min {
+			return Err(Error { message: format!("Executor::scaling() needs 1 <= min <= max, got {} and {}.", scaling.min, scaling.max) });
+		}
+		if scaling.low >= scaling.high {
+			return Err(Error { message: format!("Executor::scaling() needs low < high, got {} and {}.", scaling.low, scaling.high) });
+		}
+
+		let (queue, jobs) = jobs::job_queue();
+		let events = queue.watch(scaling.high, scaling.low);
+		let pool = Arc::new(Pool {
+			min: scaling.min,
+			live: AtomicUsize::new(0),
+			above: AtomicBool::new(false),
+			stopping: AtomicBool::new(false),
+		});
+		let workers = (0..scaling.min).map(|i| start_worker(&jobs, &pool, scaling.interval, i)).collect();
+		let supervisor = {
+			let pool = pool.clone();
+			thread::Builder::new()
+				.name("executor-supervisor".to_string())
+				.spawn(move || supervise(&jobs, &events, &pool, scaling, workers))
+				.unwrap()
+		};
+		Ok(Executor { queue: Some(queue), workers: Vec::new(), supervisor: Some((pool, supervisor)) })
+	}
+
+	/// How many workers there are right now.
+	pub fn workers(&self) -> usize {
+		match self.supervisor {
+			Some((ref pool, _)) => pool.live.load(Ordering::SeqCst),
+			None => self.workers.len(),
+		}
+	}
+
+	pub fn spawn<F, R>(&self, f: F) -> TaskHandle<R>
+		where F: FnOnce() -> R + Send + 'static, R: Send + 'static
+	{
+		let (px, cx) = sync_channel(1);
+		let job = move || {
+			let result = panic::catch_unwind(AssertUnwindSafe(f))
+				.map_err(|payload| TaskError::Panicked(panic_message(&*payload)));
+			// nobody might be waiting for it anymore
+			let _ = px.send(result);
+		};
+		// the queue only goes away in shutdown(), which takes self
+		if self.queue.as_ref().unwrap().submit(job).is_err() {
+			panic!("Executor::spawn() no worker left.");
+		}
+		TaskHandle { result: cx }
+	}
+
+	/// Waits for every submitted task to finish and stops the workers.
+	/// Returns how many tasks each worker ran.
+	pub fn shutdown(mut self) -> Vec<usize> {
+		self.stop()
+	}
+
+	fn stop(&mut self) -> Vec<usize> {
+		// the workers stop once the queue is disconnected and drained
+		self.queue = None;
+		if let Some((pool, supervisor)) = self.supervisor.take() {
+			pool.stopping.store(true, Ordering::SeqCst);
+			return supervisor.join().unwrap();
+		}
+		self.workers.drain(..).map(|w| w.join().unwrap()).collect()
+	}
+}
+
+// Adds and retires workers until the executor stops, then joins them all
+// and returns what each one ran, the retired ones included.
+fn supervise(jobs: &Jobs, events: &Consumer<Watermark>, pool: &Arc<Pool>, scaling: Scaling, mut workers: Vec<thread::JoinHandle<usize>>) -> Vec<usize> {
+	while !pool.stopping.load(Ordering::SeqCst) {
+		match events.recv_timeout(scaling.interval) {
+			Ok(Watermark::High) => pool.above.store(true, Ordering::SeqCst),
+			Ok(Watermark::Low) => pool.above.store(false, Ordering::SeqCst),
+			Err(_) => {}
+		}
+		if pool.above.load(Ordering::SeqCst) && pool.live.load(Ordering::SeqCst) < scaling.max {
+			let worker = start_worker(jobs, pool, scaling.interval, workers.len());
+			workers.push(worker);
+		}
+	}
+	workers.into_iter().map(|w| w.join().unwrap()).collect()
+}
+
+fn start_worker(jobs: &Jobs, pool: &Arc<Pool>, interval: Duration, i: usize) -> thread::JoinHandle<usize> {
+	pool.live.fetch_add(1, Ordering::SeqCst);
+	let jobs = jobs.clone();
+	let pool = pool.clone();
+	thread::Builder::new()
+		.name(format!("executor-{}", i))
+		.spawn(move || scaling_work(&jobs, &pool, interval))
+		.unwrap()
+}
+
+// jobs::work(), except that an idle worker retires if the pool may shrink.
+// The tasks catch their own panics.
+fn scaling_work(jobs: &Jobs, pool: &Pool, interval: Duration) -> usize {
+	let mut ran = 0;
+	loop {
+		match jobs.recv_timeout(interval) {
+			Ok(job) => {
+				job();
+				ran += 1;
+			}
+			Err(RecvTimeoutError::Timeout) => {
+				if pool.retire() {
+					return ran;
+				}
+			}
+			Err(_) => {
+				pool.live.fetch_sub(1, Ordering::SeqCst);
+				return ran;
+			}
+		}
+	}
+}
+
+impl Drop for Executor {
+	fn drop(&mut self) {
+		self.stop();
+	}
+}
+
+impl<R: Send> TaskHandle<R> {
+	/// Blocks until the task is done and returns its result.
+	pub fn join(self) -> Result<R, TaskError> {
+		match self.result.recv() {
+			Ok(result) => result,
+			Err(RecvError::ProducerPanicked(message)) => Err(TaskError::Panicked(message)),
+			Err(_) => Err(TaskError::Cancelled),
+		}
+	}
+
+	/// Whether the result is ready, so `join()` would not block.
+	pub fn is_finished(&self) -> bool {
+		match self.result.size() {
+			Ok(n) => n > 0,
+			Err(_) => false,
+		}
+	}
+
+	/// The result if the task is done, the handle back otherwise.
+	pub fn try_join(self) -> Result<Result<R, TaskError>, Self> {
+		match self.result.try_recv() {
+			Ok(result) => Ok(result),
+			Err(TryRecvError::Empty) => Err(self),
+			Err(_) => Ok(Err(TaskError::Cancelled)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn tasks_return_their_results() {
+		let executor = Executor::new(4).unwrap();
+		let handles: Vec<_> = (0..20u64).map(|i| executor.spawn(move || i * i)).collect();
+		let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+		assert_eq!(results, (0..20u64).map(|i| i * i).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn a_panicking_task_leaves_the_worker_alive() {
+		let executor = Executor::new(1).unwrap();
+		let bad = executor.spawn(|| -> u32 { panic!("task {} failed", 1) });
+		let good = executor.spawn(|| 2);
+		assert_eq!(bad.join(), Err(TaskError::Panicked("task 1 failed".to_string())));
+		assert_eq!(good.join(), Ok(2));
+	}
+
+	#[test]
+	fn an_executor_without_threads_is_rejected() {
+		assert_eq!(Executor::new(0).err().unwrap().to_string(), "Executor::new() needs at least 1 thread, got 0.");
+	}
+
+	fn scaling() -> Scaling {
+		Scaling { min: 1, max: 4, high: 4, low: 1, interval: Duration::from_millis(5) }
+	}
+
+	// polls `executor.workers()` until it is `n`, for up to a few seconds
+	fn wait_for_workers(executor: &Executor, n: usize) -> bool {
+		(0..1000).any(|_| {
+			thread::sleep(Duration::from_millis(5));
+			executor.workers() == n
+		})
+	}
+
+	#[test]
+	fn the_pool_grows_under_load_and_shrinks_when_idle() {
+		let executor = Executor::scaling(scaling()).unwrap();
+		assert_eq!(executor.workers(), 1);
+
+		let handles: Vec<_> = (0..40).map(|i| executor.spawn(move || {
+			thread::sleep(Duration::from_millis(5));
+			i
+		})).collect();
+		assert!(wait_for_workers(&executor, 4), "the pool did not grow to its maximum");
+
+		let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+		assert_eq!(results, (0..40).collect::<Vec<_>>());
+		assert!(wait_for_workers(&executor, 1), "the pool did not shrink to its minimum");
+
+		let ran = executor.shutdown();
+		assert!(ran.len() >= 4);
+		assert_eq!(ran.iter().sum::<usize>(), 40);
+	}
+
+	#[test]
+	fn a_scaling_executor_needs_sensible_bounds() {
+		let bad = |scaling| Executor::scaling(scaling).err().unwrap().to_string();
+		assert_eq!(bad(Scaling { min: 0, ..scaling() }), "Executor::scaling() needs 1 <= min <= max, got 0 and 4.");
+		assert_eq!(bad(Scaling { max: 0, ..scaling() }), "Executor::scaling() needs 1 <= min <= max, got 1 and 0.");
+		assert_eq!(bad(Scaling { low: 4, ..scaling() }), "Executor::scaling() needs low < high, got 4 and 4.");
+	}
+
+	#[test]
+	fn shutdown_finishes_submitted_tasks() {
+		let executor = Executor::new(2).unwrap();
+		let handles: Vec<_> = (0..10).map(|i| executor.spawn(move || {
+			thread::sleep(Duration::from_millis(2));
+			i
+		})).collect();
+
+		let ran = executor.shutdown();
+		assert_eq!(ran.iter().sum::<usize>(), 10);
+		for (i, handle) in handles.into_iter().enumerate() {
+			assert!(handle.is_finished());
+			assert_eq!(handle.try_join().ok().unwrap(), Ok(i));
+		}
+	}
+}
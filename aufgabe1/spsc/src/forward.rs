@@ -0,0 +1,254 @@
+//! `forward()` moves every value from one channel into another on a thread
+//! of its own, the glue between two stages that were built separately.
+//!
+//! Backpressure carries over: the thread takes a value out of the source
+//! only after the previous one went into the destination, so a full bounded
+//! destination holds values back in the source. The thread stops when the
+//! source is disconnected and drained, when the destination has no consumer
+//! left, or when the `ForwardHandle` is stopped or dropped. A value the
+//! thread had already taken out of the source when it stopped is handed back
+//! by `join()` or `stop()`, only dropping the handle drops it.
+//!
+//! When the source ends because its producer panicked, it was poisoned or
+//! shut down, the destination ends the same way, like the branches of
+//! `Consumer::tee()` do.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::select::Signal;
+use super::{Consumer, Producer, SendError, TryRecvError, TrySendError};
+
+// How often a thread waiting for room in the destination looks at the stop
+// flag, stop() cannot wake it up through the destination's condvar.
+const STOP_CHECK: Duration = Duration::from_millis(10);
+
+struct Control {
+	stopped: AtomicBool,
+	forwarded: AtomicUsize,
+	// registered with the source channel, raised by stop() as well
+	signal: Arc<Signal>,
+}
+
+// Both ends, and the value in hand when the thread stopped.
+type Ends<T> = (Consumer<T>, Producer<T>, Option<T>);
+
+/// Owns the forwarding thread started by `forward()`. Dropping it stops the
+/// thread.
+pub struct ForwardHandle<T: Send> {
+	control: Arc<Control>,
+	thread: Option<thread::JoinHandle<Ends<T>>>,
+}
+
+/// Starts a thread moving the values of `cx` into `px`.
+pub fn forward<T: Send + 'static>(cx: Consumer<T>, px: Producer<T>) -> ForwardHandle<T> {
+	let control = Arc::new(Control { stopped: AtomicBool::new(false), forwarded: AtomicUsize::new(0), signal: Signal::new() });
+	cx.shared.lock().unwrap().watchers.push(control.signal.clone());
+
+	let thread = {
+		let control = control.clone();
+		thread::spawn(move || {
+			let left = run(&cx, &px, &control);
+			cx.shared.lock().unwrap().watchers.retain(|w| !Arc::ptr_eq(w, &control.signal));
+			(cx, px, left)
+		})
+	};
+	ForwardHandle { control, thread: Some(thread) }
+}
+
+// Returns the value in hand if it could not be delivered.
+fn run<T: Send>(cx: &Consumer<T>, px: &Producer<T>, control: &Control) -> Option<T> {
+	loop {
+		// lowered before looking, so a value arriving in between raises it
+		// again and wait() returns right away
+		control.signal.lower();
+		if control.stopped.load(Ordering::SeqCst) {
+			return None;
+		}
+		let received = match cx.try_recv() {
+			Ok(value) => Ok(value),
+			Err(TryRecvError::Empty) => {
+				control.signal.wait();
+				continue;
+			}
+			// try_recv() only says that the stream ended, recv() says how
+			Err(_) => cx.recv(),
+		};
+		match received {
+			Ok(value) => {
+				if let Err(value) = send(px, value, control) {
+					return Some(value);
+				}
+				control.forwarded.fetch_add(1, Ordering::SeqCst);
+			}
+			Err(e) => {
+				px.pass_on(&e);
+				return None;
+			}
+		}
+	}
+}
+
+// Like px.send(), but gives up with the value when the handle is stopped
+// while the destination is full.
+fn send<T: Send>(px: &Producer<T>, mut value: T, control: &Control) -> Result<(), T> {
+	loop {
+		match px.try_send(value) {
+			Ok(()) => return Ok(()),
+			Err(TrySendError::Full(v)) => value = v,
+			Err(TrySendError::Disconnected(v)) | Err(TrySendError::Poisoned(v)) => return Err(v),
+		}
+		if control.stopped.load(Ordering::SeqCst) {
+			return Err(value);
+		}
+		let state = match px.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("forward() could not lock mutex."),
+		};
+		drop(px.shared.wait_timeout(&px.shared.not_full, state, STOP_CHECK));
+	}
+}
+
+impl<T: Send> ForwardHandle<T> {
+	/// How many values went through so far.
+	pub fn forwarded(&self) -> usize {
+		self.control.forwarded.load(Ordering::SeqCst)
+	}
+
+	/// Whether the thread is done, because one of the sides went away.
+	pub fn is_finished(&self) -> bool {
+		self.thread.as_ref().is_none_or(|t| t.is_finished())
+	}
+
+	/// Waits until the source is disconnected and drained, or the
+	/// destination has no consumer left. Returns how many values went
+	/// through, or the value that was on its way when the destination went
+	/// away.
+	pub fn join(mut self) -> Result<usize, SendError<T>> {
+		match self.wait() {
+			Some((_, _, Some(value))) => Err(SendError(value)),
+			_ => Ok(self.forwarded()),
+		}
+	}
+
+	/// Stops forwarding and hands back both ends, so they can be used
+	/// directly or forwarded elsewhere, together with the value that was
+	/// waiting for room in the destination, if any.
+	pub fn stop(mut self) -> Ends<T> {
+		self.request_stop();
+		self.wait().unwrap()
+	}
+
+	fn request_stop(&self) {
+		self.control.stopped.store(true, Ordering::SeqCst);
+		self.control.signal.raise();
+	}
+
+	fn wait(&mut self) -> Option<Ends<T>> {
+		self.thread.take().map(|t| match t.join() {
+			Ok(ends) => ends,
+			Err(_) => panic!("ForwardHandle::join() forwarding thread panicked."),
+		})
+	}
+}
+
+impl<T: Send> Drop for ForwardHandle<T> {
+	fn drop(&mut self) {
+		if self.thread.is_some() {
+			self.request_stop();
+			self.wait();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::{channel, sync_channel, RecvError};
+	use std::time::Duration;
+
+	#[test]
+	fn values_arrive_in_order_until_the_source_disconnects() {
+		let (px, source) = channel(0);
+		let (dest, cx) = sync_channel(2);
+		let handle = forward(source, dest);
+
+		let producer_thread = thread::spawn(move || {
+			for i in 0..100 {
+				px.send(i).unwrap();
+			}
+		});
+		let received: Vec<u32> = (0..100).map(|_| cx.recv().unwrap()).collect();
+		producer_thread.join().unwrap();
+
+		assert_eq!(received, (0..100).collect::<Vec<_>>());
+		assert_eq!(handle.join().ok(), Some(100));
+		assert_eq!(cx.recv(), Err(RecvError::Disconnected));
+	}
+
+	#[test]
+	fn a_panicking_source_producer_reaches_the_destination() {
+		let (px, source) = channel::<u32>(0);
+		let (dest, cx) = channel(0);
+		let handle = forward(source, dest);
+
+		let producer_thread = px.spawn(|px| {
+			px.send(1).unwrap();
+			panic!("source on fire");
+		});
+		assert!(producer_thread.join().is_err());
+
+		assert_eq!(handle.join().ok(), Some(1));
+		assert_eq!(cx.recv(), Ok(1));
+		assert_eq!(cx.recv(), Err(RecvError::ProducerPanicked("source on fire".to_string())));
+	}
+
+	#[test]
+	fn stop_hands_back_both_ends() {
+		let (px, source) = channel(0);
+		let (dest, cx) = channel(0);
+		let handle = forward(source, dest);
+
+		px.send(1).unwrap();
+		assert_eq!(cx.recv(), Ok(1));
+
+		let (source, dest, left) = handle.stop();
+		assert_eq!(left, None);
+		px.send(2).unwrap();
+		assert_eq!(cx.try_recv(), Err(TryRecvError::Empty));
+		assert_eq!(source.recv(), Ok(2));
+		dest.send(3).unwrap();
+		assert_eq!(cx.recv(), Ok(3));
+	}
+
+	#[test]
+	fn forwarding_ends_without_a_destination() {
+		let (px, source) = channel(0);
+		let (dest, cx) = channel(0);
+		let handle = forward(source, dest);
+		drop(cx);
+		px.send(1).unwrap();
+		assert_eq!(handle.join().err().unwrap().0, 1);
+	}
+
+	#[test]
+	fn stop_does_not_wait_for_room_in_the_destination() {
+		let (px, source) = channel(0);
+		let (dest, cx) = sync_channel(1);
+		let handle = forward(source, dest);
+
+		px.send(1).unwrap();
+		px.send(2).unwrap();
+		// 1 fills the destination, 2 is in hand and stays there
+		while cx.size().unwrap() == 0 || px.size().unwrap() > 0 {
+			thread::sleep(Duration::from_millis(1));
+		}
+
+		let (_, _, left) = handle.stop();
+		assert_eq!(left, Some(2));
+		assert_eq!(cx.recv(), Ok(1));
+	}
+}
@@ -0,0 +1,200 @@
+//! The work queue specialized to its most common use, handing closures to
+//! worker threads:
+//!
+//! ```
+//! let (queue, jobs) = spsc::jobs::job_queue();
+//! let worker = std::thread::spawn(move || spsc::jobs::work(&jobs));
+//! queue.submit(|| println!("hello from a worker")).unwrap();
+//! drop(queue);
+//! assert_eq!(worker.join().unwrap().ran, 1);
+//! ```
+
+use std::hash::Hash;
+use std::panic::{self, AssertUnwindSafe};
+
+use super::keyed::{self, KeyedProducer};
+use super::watermark::{self, Watermark};
+use super::{channel, sync_channel, Consumer, Producer, SendError};
+
+pub type Job = Box<dyn FnOnce() + Send>;
+
+/// The receiving end, shared by the workers.
+pub type Jobs = Consumer<Job>;
+
+/// What `work()` did before the queue ran dry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Worked {
+	/// Jobs taken from the queue, including those that panicked.
+	pub ran: usize,
+	pub panicked: usize,
+}
+
+/// The submitting end of a job queue.
+#[derive(Clone)]
+pub struct JobQueue {
+	inner: Producer<Job>,
+}
+
+/// The submitting end of a keyed job queue: jobs submitted with the same key
+/// always run on the same worker, one after the other, so whatever state the
+/// worker keeps per key needs no lock of its own.
+#[derive(Clone)]
+pub struct KeyedJobQueue {
+	inner: KeyedProducer<Job>,
+}
+
+/// An unbounded job queue.
+pub fn job_queue() -> (JobQueue, Jobs) {
+	let (px, cx) = channel(0);
+	(JobQueue { inner: px }, cx)
+}
+
+/// A job queue where `submit()` blocks while `bound` jobs are waiting.
+pub fn sync_job_queue(bound: usize) -> (JobQueue, Jobs) {
+	let (px, cx) = sync_channel(bound);
+	(JobQueue { inner: px }, cx)
+}
+
+/// A keyed job queue for `workers` workers. Every worker gets its own
+/// `Jobs`, which must not be shared with another worker.
+pub fn keyed_job_queue(workers: usize) -> (KeyedJobQueue, Vec<Jobs>) {
+	let (inner, jobs) = keyed::keyed_channel(workers);
+	(KeyedJobQueue { inner }, jobs)
+}
+
+impl JobQueue {
+	/// Queues `f` to run on one of the workers. Fails with the boxed job if
+	/// no worker is left.
+	pub fn submit<F: FnOnce() + Send + 'static>(&self, f: F) -> Result<(), SendError<Job>> {
+		self.inner.send(Box::new(f))
+	}
+
+	/// How many jobs are waiting for a worker.
+	pub fn pending(&self) -> usize {
+		self.inner.size().unwrap()
+	}
+
+	/// Reports the number of waiting jobs crossing `high` and `low`, see
+	/// `watermark::watch()`.
+	pub fn watch(&self, high: usize, low: usize) -> Consumer<Watermark> {
+		watermark::watch(&self.inner, high, low)
+	}
+}
+
+impl KeyedJobQueue {
+	/// Queues `f` to run on the worker `key` belongs to. Fails with the boxed
+	/// job if that worker is gone.
+	pub fn submit<K: Hash + ?Sized, F: FnOnce() + Send + 'static>(&self, key: &K, f: F) -> Result<(), SendError<Job>> {
+		self.inner.send(key, Box::new(f))
+	}
+
+	/// How many jobs are waiting, over all workers.
+	pub fn pending(&self) -> usize {
+		self.inner.pending()
+	}
+}
+
+/// Runs jobs from `jobs` until every JobQueue is gone and nothing is left.
+/// A panicking job is counted and the worker goes on with the next one, the
+/// panic message goes to stderr through the panic hook as usual.
+pub fn work(jobs: &Jobs) -> Worked {
+	let mut worked = Worked { ran: 0, panicked: 0 };
+	while let Ok(job) = jobs.recv() {
+		if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+			worked.panicked += 1;
+		}
+		worked.ran += 1;
+	}
+	worked
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::{Arc, Mutex};
+	use std::thread;
+
+	#[test]
+	fn workers_run_every_job() {
+		let (queue, jobs) = sync_job_queue(4);
+		let total = Arc::new(AtomicUsize::new(0));
+
+		let workers: Vec<_> = (0..3).map(|_| {
+			let jobs = jobs.clone();
+			thread::spawn(move || work(&jobs))
+		}).collect();
+		drop(jobs);
+
+		for i in 1..=100 {
+			let total = total.clone();
+			queue.submit(move || {
+				total.fetch_add(i, Ordering::SeqCst);
+			}).unwrap();
+		}
+		drop(queue);
+
+		let ran: usize = workers.into_iter().map(|w| w.join().unwrap().ran).sum();
+		assert_eq!(ran, 100);
+		assert_eq!(total.load(Ordering::SeqCst), 5050);
+	}
+
+	#[test]
+	fn a_panicking_job_leaves_the_worker_alive() {
+		let (queue, jobs) = job_queue();
+		let total = Arc::new(AtomicUsize::new(0));
+		for i in 0..5 {
+			let total = total.clone();
+			queue.submit(move || {
+				if i == 1 {
+					panic!("job {} failed", i);
+				}
+				total.fetch_add(1, Ordering::SeqCst);
+			}).unwrap();
+		}
+		drop(queue);
+
+		let worker = thread::spawn(move || work(&jobs));
+		assert_eq!(worker.join().unwrap(), Worked { ran: 5, panicked: 1 });
+		assert_eq!(total.load(Ordering::SeqCst), 4);
+	}
+
+	#[test]
+	fn submit_without_workers_hands_the_job_back() {
+		let (queue, jobs) = job_queue();
+		drop(jobs);
+		let job = queue.submit(|| ()).unwrap_err().0;
+		job();
+		assert_eq!(queue.pending(), 0);
+	}
+
+	#[test]
+	fn jobs_with_the_same_key_stay_on_one_worker() {
+		let (queue, jobs) = keyed_job_queue(4);
+		let seen = Arc::new(Mutex::new(Vec::new()));
+
+		let workers: Vec<_> = jobs.into_iter().enumerate().map(|(id, jobs)| {
+			thread::Builder::new().name(id.to_string()).spawn(move || work(&jobs)).unwrap()
+		}).collect();
+
+		for i in 0..200u32 {
+			let seen = seen.clone();
+			let key = i % 10;
+			queue.submit(&key, move || {
+				let worker = thread::current().name().unwrap().to_string();
+				seen.lock().unwrap().push((key, i, worker));
+			}).unwrap();
+		}
+		drop(queue);
+
+		let ran: usize = workers.into_iter().map(|w| w.join().unwrap().ran).sum();
+		assert_eq!(ran, 200);
+		let seen = seen.lock().unwrap();
+		for key in 0..10 {
+			let runs: Vec<_> = seen.iter().filter(|s| s.0 == key).collect();
+			assert!(runs.iter().all(|s| s.2 == runs[0].2), "key {} moved between workers", key);
+			assert!(runs.windows(2).all(|w| w[0].1 < w[1].1), "key {} ran out of order", key);
+		}
+	}
+}
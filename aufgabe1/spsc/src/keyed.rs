@@ -0,0 +1,122 @@
+//! A channel split into shards by key. `send()` hashes the key to pick one
+//! of the shards, and every shard is a channel of its own with its own
+//! consumer, so all messages for a key arrive at the same consumer and in the
+//! order they were sent, while different keys spread over as many threads as
+//! there are shards.
+//!
+//! The producer can be cloned. Ordering is per producer and key: two
+//! producers sending for the same key interleave like on any other channel.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use super::{channel, sync_channel, Consumer, Producer, SendError};
+
+pub struct KeyedProducer<T: Send> {
+	shards: Vec<Producer<T>>,
+}
+
+/// Creates a keyed channel with `shards` unbounded shards and returns one
+/// consumer per shard.
+pub fn keyed_channel<T: Send>(shards: usize) -> (KeyedProducer<T>, Vec<Consumer<T>>) {
+	let (shards, consumers) = (0..shards.max(1)).map(|_| channel(0)).unzip();
+	(KeyedProducer { shards }, consumers)
+}
+
+/// Like `keyed_channel()`, but `send()` blocks while the shard for the key
+/// holds `bound` messages.
+pub fn keyed_sync_channel<T: Send>(shards: usize, bound: usize) -> (KeyedProducer<T>, Vec<Consumer<T>>) {
+	let (shards, consumers) = (0..shards.max(1)).map(|_| sync_channel(bound)).unzip();
+	(KeyedProducer { shards }, consumers)
+}
+
+impl<T: Send> KeyedProducer<T> {
+
+	/// Sends `value` to the shard `key` belongs to. Fails if that shard's
+	/// consumer is gone.
+	pub fn send<K: Hash + ?Sized>(&self, key: &K, value: T) -> Result<(), SendError<T>> {
+		self.shards[self.shard(key)].send(value)
+	}
+
+	/// The index of the consumer that receives the messages for `key`.
+	pub fn shard<K: Hash + ?Sized>(&self, key: &K) -> usize {
+		let mut hasher = DefaultHasher::new();
+		key.hash(&mut hasher);
+		(hasher.finish() % self.shards.len() as u64) as usize
+	}
+
+	pub fn shards(&self) -> usize {
+		self.shards.len()
+	}
+
+	/// How many messages are waiting, over all shards.
+	pub fn pending(&self) -> usize {
+		self.shards.iter().map(|shard| shard.size().unwrap()).sum()
+	}
+}
+
+impl<T: Send> Clone for KeyedProducer<T> {
+	fn clone(&self) -> Self {
+		KeyedProducer { shards: self.shards.clone() }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::collections::HashMap;
+	use std::thread;
+
+	#[test]
+	fn every_key_keeps_its_order_on_one_shard() {
+		let (px, consumers) = keyed_sync_channel(3, 8);
+		let keys: Vec<_> = (0..2u32).flat_map(|w| (0..7u32).map(move |k| (w, k))).collect();
+		let shard_of: HashMap<_, _> = keys.iter().map(|key| (*key, px.shard(key))).collect();
+
+		let readers: Vec<_> = consumers.into_iter().map(|cx| {
+			thread::spawn(move || {
+				let mut received = Vec::new();
+				while let Ok(v) = cx.recv() {
+					received.push(v);
+				}
+				received
+			})
+		}).collect();
+
+		let writers: Vec<_> = (0..2u32).map(|w| {
+			let px = px.clone();
+			thread::spawn(move || {
+				for i in 0..500u32 {
+					let key = (w, i % 7);
+					px.send(&key, (key, i)).unwrap();
+				}
+			})
+		}).collect();
+		for writer in writers {
+			writer.join().unwrap();
+		}
+		drop(px);
+
+		let mut total = 0;
+		for (shard, reader) in readers.into_iter().enumerate() {
+			let received = reader.join().unwrap();
+			total += received.len();
+			assert!(received.iter().all(|&(key, _)| shard_of[&key] == shard));
+			for key in received.iter().map(|&(key, _)| key) {
+				let per_key: Vec<_> = received.iter().filter(|r| r.0 == key).map(|r| r.1).collect();
+				assert!(per_key.windows(2).all(|w| w[0] < w[1]), "{:?} out of order", key);
+			}
+		}
+		assert_eq!(total, 1000);
+	}
+
+	#[test]
+	fn send_fails_when_the_shard_consumer_is_gone() {
+		let (px, mut consumers) = keyed_channel::<u32>(2);
+		let lost = px.shard("lost");
+		consumers.remove(lost);
+		assert!(px.send("lost", 1).is_err());
+		assert_eq!(px.pending(), 0);
+	}
+}
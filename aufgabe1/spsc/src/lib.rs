@@ -0,0 +1,1772 @@
+use std::sync::{Arc, Condvar, LockResult, Mutex, MutexGuard, PoisonError};
+use std::collections::VecDeque;
+use std::any::Any;
+use std::cell::Cell;
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::fmt;
+use std::mem;
+use std::ptr;
+
+pub mod adapters;
+pub mod age;
+pub mod broadcast;
+pub mod buffered;
+pub mod bytes;
+pub mod cancel;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod clock;
+pub mod compat;
+pub mod demos;
+pub mod duplex;
+pub mod executor;
+pub mod forward;
+pub mod jobs;
+pub mod keyed;
+pub mod pool;
+pub mod priority;
+pub mod rwlock;
+pub mod record;
+pub mod reqresp;
+pub mod scope;
+pub mod select;
+pub mod semaphore;
+pub mod session;
+pub mod seqlock;
+pub mod shutdown;
+pub mod sigsafe;
+#[cfg(feature = "test-util")]
+pub mod testing;
+pub mod tick;
+pub mod triple;
+pub mod watermark;
+
+pub use scope::scope;
+
+/*
+	Ideas and code snippets taken from:
+
+	https://stackoverflow.com/questions/47092072/one-mutable-borrow-and-multiple-immutable-borrows
+	https://gist.github.com/LeoTindall/e6d40782b05dc8ac40faf3a0405debd3
+	https://doc.rust-lang.org/std/sync/struct.Mutex.html
+	https://doc.rust-lang.org/std/sync/struct.Condvar.html
+*/
+
+#[derive(Debug)]
+pub struct Error {
+	message: String
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.message)
+	}
+}
+
+pub struct SendError<T>(pub T);
+
+// like std, so unwrap() works for values without Debug, boxed closures say
+impl<T> fmt::Debug for SendError<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("SendError(..)")
+	}
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvError {
+	/// Every producer is gone and the queue has been drained.
+	Disconnected,
+	/// A producer panicked, see `Producer::propagate_panics()`. Carries the
+	/// panic message.
+	ProducerPanicked(String),
+	/// A thread panicked while it held the channel locked, see
+	/// `Consumer::clear_poison()`.
+	Poisoned,
+	/// The queue is drained and the channel's ShutdownToken was triggered,
+	/// see shutdown.rs.
+	Shutdown,
+	/// The CancelToken passed to `Consumer::recv_cancellable()` was
+	/// cancelled, see cancel.rs.
+	Cancelled,
+}
+
+impl fmt::Display for RecvError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			RecvError::Disconnected => write!(f, "Consumer::recv() all producers disconnected."),
+			RecvError::ProducerPanicked(ref message) => write!(f, "Consumer::recv() producer panicked: {}", message),
+			RecvError::Poisoned => write!(f, "Consumer::recv() channel is poisoned."),
+			RecvError::Shutdown => write!(f, "Consumer::recv() channel was shut down."),
+			RecvError::Cancelled => write!(f, "Consumer::recv_cancellable() was cancelled."),
+		}
+	}
+}
+
+/// Returned by `Producer::try_send()`. Every variant hands the value back.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+	Full(T),
+	Disconnected(T),
+	Poisoned(T),
+}
+
+/// Returned by `Consumer::try_recv()`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+	Empty,
+	Disconnected,
+	Poisoned,
+}
+
+/// Returned by `Producer::claim()`, `Producer::write_chunk()` and
+/// `Consumer::read_chunk()` on a poisoned channel, see
+/// `Producer::is_poisoned()`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Poisoned;
+
+impl fmt::Display for Poisoned {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "channel is poisoned.")
+	}
+}
+
+/// Returned by `Consumer::recv_timeout()`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+	Timeout,
+	Disconnected,
+	Poisoned,
+}
+
+// Everything behind the mutex. The producer and consumer counts start at one
+// each, so a half created with Producer::new() or Consumer::new() behaves as
+// if its counterpart was alive forever.
+struct State<T> {
+	queue: VecDeque<T>,
+	// None for channel(), Some(n) for sync_channel(n). A bound of zero makes
+	// every send() wait until its value has been received (rendezvous).
+	bound: Option<usize>,
+	producers: usize,
+	consumers: usize,
+	// consumers currently blocked in recv(), so that try_send() on a
+	// rendezvous channel knows whether somebody is there to take the value
+	waiting: usize,
+	// producers blocked in send() on a full channel go on in the order they
+	// arrived: each takes a ticket and waits until it is being served
+	tickets: u64,
+	serving: u64,
+	// slots held by Permits, they count as queued for the bound
+	reserved: usize,
+	sent: u64,
+	received: u64,
+	// called under the lock for every value that becomes visible to the
+	// consumers and every value taken out, see record.rs
+	hook: Option<Hook<T>>,
+	hook_failure: Option<String>,
+	#[cfg(feature = "chaos")]
+	chaos: Option<chaos::Chaos>,
+	// None measures timeouts in real time, see clock.rs
+	clock: Option<Arc<dyn clock::Clock>>,
+	// see Producer::propagate_panics()
+	propagate_panics: bool,
+	panicked: Option<String>,
+	// raised for every published value and when either side disconnects,
+	// see select.rs
+	watchers: Vec<Arc<select::Signal>>,
+	shutdown: Option<shutdown::Attachment>,
+	watermarks: Option<watermark::Watermarks>,
+	// enqueue timestamps, see age.rs
+	ages: Option<age::Ages>,
+	// see Consumer::on_drop_drain()
+	drain: Option<Drain<T>>,
+	#[cfg(feature = "test-util")]
+	must_drain: bool,
+	// a thread panicked while holding the lock, see Shared::recover()
+	poisoned: bool,
+}
+
+type Hook<T> = Box<dyn FnMut(Event, &T) + Send>;
+type Drain<T> = Box<dyn FnMut(T) + Send>;
+
+// Runs the hook under the channel lock. A panic in it (in user code like a
+// Debug impl) must not unwind through the lock and poison the channel, so it
+// is caught, the hook is removed and the message is kept in `failure`.
+fn run_hook<T>(hook: &mut Option<Hook<T>>, failure: &mut Option<String>, event: Event, value: &T) {
+	let panicked = match hook.as_mut() {
+		Some(f) => panic::catch_unwind(AssertUnwindSafe(|| f(event, value))).err(),
+		None => return,
+	};
+	if let Some(payload) = panicked {
+		*hook = None;
+		*failure = Some(panic_message(&*payload));
+	}
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+	if let Some(s) = payload.downcast_ref::<&str>() {
+		s.to_string()
+	} else if let Some(s) = payload.downcast_ref::<String>() {
+		s.clone()
+	} else {
+		"Box<dyn Any>".to_string()
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Event {
+	Sent,
+	Received,
+}
+
+impl<T> State<T> {
+	// Makes the last `n` values of the queue visible to the consumers.
+	fn publish(&mut self, n: usize) {
+		self.sent += n as u64;
+		if self.hook.is_some() {
+			let start = self.queue.len() - n;
+			for value in self.queue.range(start..) {
+				run_hook(&mut self.hook, &mut self.hook_failure, Event::Sent, value);
+			}
+		}
+		if let Some(ref mut marks) = self.watermarks {
+			marks.check(self.queue.len());
+		}
+		if let Some(ref mut ages) = self.ages {
+			ages.published(n, clock::now(&self.clock));
+		}
+		self.notify_watchers();
+	}
+
+	fn notify_watchers(&self) {
+		for signal in &self.watchers {
+			signal.raise();
+		}
+	}
+
+	fn push(&mut self, value: T) {
+		self.queue.push_back(value);
+		self.publish(1);
+	}
+
+	// The time according to the channel's clock, see the clock module.
+	fn now(&self) -> Instant {
+		clock::now(&self.clock)
+	}
+
+	// Queued values plus reserved slots, what the bound applies to.
+	fn occupied(&self) -> usize {
+		self.queue.len() + self.reserved
+	}
+
+	// Nothing more is going to arrive once the queue is empty.
+	fn disconnected(&self) -> bool {
+		self.producers == 0 || self.panicked.is_some() || self.shut_down()
+	}
+
+	fn shut_down(&self) -> bool {
+		self.shutdown.as_ref().is_some_and(|attachment| attachment.is_triggered())
+	}
+
+	fn pop(&mut self) -> Option<T> {
+		let value = self.queue.pop_front()?;
+		self.received += 1;
+		run_hook(&mut self.hook, &mut self.hook_failure, Event::Received, &value);
+		if let Some(ref mut marks) = self.watermarks {
+			marks.check(self.queue.len());
+		}
+		if let Some(ref mut ages) = self.ages {
+			ages.received(clock::now(&self.clock));
+		}
+		Some(value)
+	}
+
+	// Swaps the whole queue with `buffer`, which takes over the values, and
+	// does for each of them what pop() does.
+	fn pop_all(&mut self, buffer: &mut VecDeque<T>) -> usize {
+		mem::swap(&mut self.queue, buffer);
+		let n = buffer.len();
+		self.received += n as u64;
+		if self.hook.is_some() {
+			for value in buffer.iter() {
+				run_hook(&mut self.hook, &mut self.hook_failure, Event::Received, value);
+			}
+		}
+		if let Some(ref mut marks) = self.watermarks {
+			marks.check(0);
+		}
+		if let Some(ref mut ages) = self.ages {
+			let now = clock::now(&self.clock);
+			for _ in 0..n {
+				ages.received(now);
+			}
+		}
+		n
+	}
+
+	// Whether fault injection wants `fault` to happen right here.
+	#[cfg(feature = "chaos")]
+	fn chaos(&mut self, fault: chaos::Fault) -> bool {
+		self.chaos.as_mut().is_some_and(|c| c.roll(fault))
+	}
+}
+
+thread_local! {
+	// the channel whose panics Producer::run_catching() reports itself, with
+	// the message, on the thread it runs on. Other channels' producers
+	// dropped on that thread report as usual.
+	static CATCHING_PANICS: Cell<*const ()> = const { Cell::new(ptr::null()) };
+}
+
+struct Shared<T> {
+	state: Mutex<State<T>>,
+	// signalled when a value is pushed or the last producer goes away
+	not_empty: Condvar,
+	// signalled when a value is popped or the last consumer goes away
+	not_full: Condvar,
+}
+
+impl<T> Shared<T> {
+	// Tells channels apart, see CATCHING_PANICS.
+	fn identity(&self) -> *const () {
+		self as *const Self as *const ()
+	}
+
+	fn new(capacity: usize, bound: Option<usize>) -> Arc<Self> {
+		Arc::new(Shared {
+			state: Mutex::new(State {
+				queue: VecDeque::with_capacity(capacity),
+				bound,
+				producers: 1,
+				consumers: 1,
+				waiting: 0,
+				tickets: 0,
+				serving: 0,
+				reserved: 0,
+				sent: 0,
+				received: 0,
+				hook: None,
+				hook_failure: None,
+				#[cfg(feature = "chaos")]
+				chaos: None,
+				clock: None,
+				propagate_panics: false,
+				panicked: None,
+				watchers: Vec::new(),
+				shutdown: None,
+				watermarks: None,
+				ages: None,
+				drain: None,
+				#[cfg(feature = "test-util")]
+				must_drain: false,
+				poisoned: false,
+			}),
+			not_empty: Condvar::new(),
+			not_full: Condvar::new(),
+		})
+	}
+
+	// Never fails, see recover().
+	fn lock(&self) -> LockResult<MutexGuard<'_, State<T>>> {
+		Ok(self.recover(self.state.lock()))
+	}
+
+	// A thread that panics while it holds the lock (in a Slot, say) poisons
+	// the Mutex. That is turned into the channel's own poisoned flag, which
+	// clear_poison() can reset, and the Mutex itself is cleared again.
+	fn recover<'a>(&self, result: LockResult<MutexGuard<'a, State<T>>>) -> MutexGuard<'a, State<T>> {
+		result.unwrap_or_else(|e| {
+			self.state.clear_poison();
+			let mut state = e.into_inner();
+			self.poison(&mut state);
+			state
+		})
+	}
+
+	// Marks the channel as poisoned and wakes up everybody blocked on it, so
+	// they can report it.
+	fn poison(&self, state: &mut State<T>) {
+		state.poisoned = true;
+		state.notify_watchers();
+		self.not_empty.notify_all();
+		self.not_full.notify_all();
+	}
+
+	// Condvar::wait(), except that with fault injection it sometimes returns
+	// without a notification, like a spurious wakeup.
+	fn wait<'a>(&self, condvar: &Condvar, state: MutexGuard<'a, State<T>>) -> LockResult<MutexGuard<'a, State<T>>> {
+		#[cfg(feature = "chaos")]
+		let state = {
+			let mut state = state;
+			if state.chaos(chaos::Fault::SpuriousWakeup) {
+				return Ok(self.wait_timeout(condvar, state, Duration::from_micros(50)));
+			}
+			state
+		};
+		Ok(self.recover(condvar.wait(state)))
+	}
+
+	fn wait_timeout<'a>(&self, condvar: &Condvar, state: MutexGuard<'a, State<T>>, timeout: Duration) -> MutexGuard<'a, State<T>> {
+		match condvar.wait_timeout(state, timeout) {
+			Ok((state, _)) => state,
+			Err(e) => self.recover(Err(PoisonError::new(e.into_inner().0))),
+		}
+	}
+
+	// Sleeps before an operation if fault injection says so.
+	#[cfg(feature = "chaos")]
+	fn chaos_delay(&self) {
+		let delay = match self.lock() {
+			Ok(mut state) => state.chaos.as_mut().and_then(|c| c.delay()),
+			Err(_) => None,
+		};
+		if let Some(delay) = delay {
+			thread::sleep(delay);
+		}
+	}
+}
+
+// see testing::assert_drained()
+#[cfg(feature = "test-util")]
+impl<T> Drop for Shared<T> {
+	fn drop(&mut self) {
+		let state = self.state.get_mut().unwrap_or_else(|e| e.into_inner());
+		if state.must_drain && !state.queue.is_empty() && !thread::panicking() {
+			panic!("channel dropped with {} of {} values never received", state.queue.len(), state.sent);
+		}
+	}
+}
+
+// All three of these types are wrapped around a generic type T.
+// T is required to be Send (a marker trait automatically implemented when
+// it is safe to do so) because it denotes types that are safe to move between
+// threads, which is the whole point of the WorkQueue.
+
+/// A generic work queue. Values are moved through it, so anything that can
+/// be sent to another thread works, boxed closures included (see jobs.rs).
+/// Any producer of work can add elements and any worker can consume them.
+/// Both halves implement Clone so that they can be distributed among threads.
+/// Once every Producer is dropped the consumers drain the queue and then get
+/// an error instead of blocking, and once every Consumer is dropped send()
+/// hands the value back.
+pub struct Producer<T: Send> {
+	shared: Arc<Shared<T>>,
+}
+
+pub struct Consumer<T: Send> {
+	shared: Arc<Shared<T>>,
+}
+
+impl<T: Send> Producer<T> {
+
+	pub fn new(capacity: usize) -> Self {
+		Self { shared: Shared::new(capacity, None) }
+	}
+
+	pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+		#[cfg(feature = "chaos")]
+		self.shared.chaos_delay();
+
+		// try to get a lock to the mutex...
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Producer::send() could not lock mutex."),
+		};
+
+		// a bounded channel blocks until a consumer made room
+		if let Some(bound) = state.bound.filter(|&b| b > 0) {
+			state = self.wait_for_room(state, bound);
+		}
+
+		if state.consumers == 0 || state.poisoned {
+			return Err(SendError(value));
+		}
+
+		state.push(value);
+		self.shared.not_empty.notify_one();
+
+		// rendezvous: wait until our value has been taken out again
+		if state.bound == Some(0) {
+			let ticket = state.sent;
+			while state.consumers > 0 && !state.poisoned && state.received < ticket {
+				state = self.shared.wait(&self.shared.not_full, state).unwrap();
+			}
+		}
+		Ok(())
+	}
+
+	// Waits until fewer than `bound` slots are occupied. Producers that were
+	// waiting before this one go first.
+	fn wait_for_room<'a>(&self, mut state: MutexGuard<'a, State<T>>, bound: usize) -> MutexGuard<'a, State<T>> {
+		let ticket = state.tickets;
+		state.tickets += 1;
+		while state.consumers > 0 && !state.poisoned && (state.serving < ticket || state.occupied() >= bound) {
+			state = self.shared.wait(&self.shared.not_full, state).unwrap();
+		}
+		state.serving += 1;
+		// the next in line might fit as well
+		self.shared.not_full.notify_all();
+		state
+	}
+
+	/// Waits for a free slot like send() and holds on to it, so the value can
+	/// be built afterwards and sent without waiting or failing. Fails if
+	/// there is no consumer left. A rendezvous channel has one slot for this,
+	/// and `Permit::send()` does not wait for the value to be received.
+	pub fn reserve(&self) -> Result<Permit<T>, SendError<()>> {
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Producer::reserve() could not lock mutex."),
+		};
+
+		if let Some(bound) = state.bound {
+			state = self.wait_for_room(state, bound.max(1));
+		}
+		if state.consumers == 0 || state.poisoned {
+			return Err(SendError(()));
+		}
+
+		state.reserved += 1;
+		Ok(Permit { shared: self.shared.clone(), used: false })
+	}
+
+	pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+		#[cfg(feature = "chaos")]
+		self.shared.chaos_delay();
+
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Producer::try_send() could not lock mutex."),
+		};
+
+		if state.poisoned {
+			return Err(TrySendError::Poisoned(value));
+		}
+		if state.consumers == 0 {
+			return Err(TrySendError::Disconnected(value));
+		}
+
+		#[cfg(feature = "chaos")]
+		{
+			if state.chaos(chaos::Fault::Full) {
+				return Err(TrySendError::Full(value));
+			}
+		}
+
+		let full = match state.bound {
+			None => false,
+			// only succeeds if a blocked consumer is going to take it
+			Some(0) => state.waiting <= state.queue.len(),
+			// producers blocked in send() were first
+			Some(bound) => state.occupied() >= bound || state.serving < state.tickets,
+		};
+		if full {
+			return Err(TrySendError::Full(value));
+		}
+
+		state.push(value);
+		self.shared.not_empty.notify_one();
+		Ok(())
+	}
+
+	pub fn capacity(&self) -> Result<usize, Error> {
+		if let Ok(state) = self.shared.lock() {
+			let capacity = state.queue.capacity();
+			Ok(capacity)
+		} else {
+			panic!("Producer::capacity() could not lock mutex.");
+		}
+	}
+
+	pub fn size(&self) -> Result<usize, Error> {
+		if let Ok(state) = self.shared.lock() {
+			let len = state.queue.len();
+			Ok(len)
+		} else {
+			panic!("Producer::size() could not lock mutex.");
+		}
+	}
+
+	/// Turns on panic propagation for this channel: when a thread holding a
+	/// Producer panics, consumers get `RecvError::ProducerPanicked` once the
+	/// queue is drained, instead of waiting for values that never come or
+	/// seeing a plain disconnect. `try_recv()` and `recv_timeout()` report
+	/// it as a disconnect.
+	pub fn propagate_panics(&self) {
+		if let Ok(mut state) = self.shared.lock() {
+			state.propagate_panics = true;
+		}
+	}
+
+	/// Moves the producer into a new thread running `f`, with panic
+	/// propagation turned on. Unlike a producer that is merely dropped during
+	/// unwinding, this also passes on the panic message, and the returned
+	/// JoinHandle still reports the panic as usual.
+	pub fn spawn<F, R>(self, f: F) -> thread::JoinHandle<R>
+		where F: FnOnce(Producer<T>) -> R + Send + 'static, R: Send + 'static, T: 'static
+	{
+		thread::spawn(move || {
+			match self.run_catching(f) {
+				Ok(result) => result,
+				Err(payload) => panic::resume_unwind(payload),
+			}
+		})
+	}
+
+	// Runs `f` with panic propagation turned on and hands the panic message
+	// to the consumers if it panics. Meant to be called at the top of a
+	// thread of its own, see spawn().
+	fn run_catching<F, R>(self, f: F) -> thread::Result<R>
+		where F: FnOnce(Producer<T>) -> R
+	{
+		self.propagate_panics();
+		let outer = CATCHING_PANICS.with(|c| c.replace(self.shared.identity()));
+		// keeps the channel connected until the message is filled in
+		let keep = self.clone();
+		let result = panic::catch_unwind(AssertUnwindSafe(move || f(self)));
+		CATCHING_PANICS.with(|c| c.set(outer));
+		if let Err(ref payload) = result {
+			if let Ok(mut state) = keep.shared.lock() {
+				state.panicked = Some(panic_message(&**payload));
+				state.notify_watchers();
+				keep.shared.not_empty.notify_all();
+			}
+		}
+		result
+	}
+
+	// For threads that forward another channel into this one: ends the
+	// stream with the error the source ended with, so the consumers here see
+	// the panic, poison or shutdown instead of a plain disconnect. A plain
+	// disconnect needs nothing, it follows once the producer is dropped.
+	pub(crate) fn pass_on(&self, error: &RecvError) {
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Producer::pass_on() could not lock mutex."),
+		};
+		match *error {
+			RecvError::ProducerPanicked(ref message) => state.panicked = Some(message.clone()),
+			RecvError::Poisoned => return self.shared.poison(&mut state),
+			RecvError::Shutdown => state.shutdown = Some(shutdown::Attachment::triggered()),
+			RecvError::Disconnected | RecvError::Cancelled => return,
+		}
+		state.notify_watchers();
+		self.shared.not_empty.notify_all();
+	}
+
+	/// Whether a thread panicked while it held the channel locked, in a Slot,
+	/// WriteChunk or ReadChunk. Every operation fails until `clear_poison()`
+	/// is called: `send()` hands the value back, the others report
+	/// `Poisoned`.
+	pub fn is_poisoned(&self) -> bool {
+		self.shared.lock().unwrap().poisoned
+	}
+
+	/// Makes a poisoned channel usable again. Whatever the panicking thread
+	/// was in the middle of was rolled back (a claimed slot or write chunk is
+	/// never published, the values of a read chunk stay queued), so this is
+	/// safe once the rest of the program has dealt with the panic.
+	pub fn clear_poison(&self) {
+		self.shared.lock().unwrap().poisoned = false;
+	}
+
+	/// Reserves the next slot at the back of the queue and hands out a
+	/// mutable reference to it, so large values can be built in place. The
+	/// value starts out as `T::default()` and becomes visible to consumers
+	/// when the returned Slot is dropped. The queue stays locked until then.
+	/// On a rendezvous channel this waits for the queue to be empty instead.
+	/// Fails on a poisoned channel.
+	pub fn claim(&self) -> Result<Slot<'_, T>, Poisoned> where T: Default {
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Producer::claim() could not lock mutex."),
+		};
+
+		if let Some(bound) = state.bound {
+			state = self.wait_for_room(state, bound.max(1));
+		}
+		if state.poisoned {
+			return Err(Poisoned);
+		}
+
+		state.queue.push_back(T::default());
+		Ok(Slot { producer: self, state })
+	}
+
+	/// Reserves up to `n` slots at once (fewer if a bounded channel does not
+	/// have that much room, possibly zero) without blocking. The slots start
+	/// out as `T::default()` and are exposed as at most two mutable slices.
+	/// Dropping the chunk publishes everything, `commit()` only a prefix.
+	/// Fails on a poisoned channel.
+	pub fn write_chunk(&self, n: usize) -> Result<WriteChunk<'_, T>, Poisoned> where T: Default {
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Producer::write_chunk() could not lock mutex."),
+		};
+		if state.poisoned {
+			return Err(Poisoned);
+		}
+
+		let len = match state.bound {
+			None => n,
+			Some(bound) => n.min(bound.max(1).saturating_sub(state.occupied())),
+		};
+		#[cfg(feature = "chaos")]
+		let len = if state.chaos(chaos::Fault::Full) { 0 } else { len };
+		for _ in 0..len {
+			state.queue.push_back(T::default());
+		}
+		Ok(WriteChunk { producer: self, state, len })
+	}
+}
+
+/// A claimed slot, see `Producer::claim()`. Publishes its value on drop,
+/// unless the thread is unwinding, in which case the half-built value is
+/// thrown away and the channel is poisoned.
+pub struct Slot<'a, T: Send + 'a> {
+	producer: &'a Producer<T>,
+	state: MutexGuard<'a, State<T>>,
+}
+
+impl<'a, T: Send> Deref for Slot<'a, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		self.state.queue.back().unwrap()
+	}
+}
+
+impl<'a, T: Send> DerefMut for Slot<'a, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		self.state.queue.back_mut().unwrap()
+	}
+}
+
+impl<'a, T: Send> Drop for Slot<'a, T> {
+	fn drop(&mut self) {
+		if thread::panicking() {
+			self.state.queue.pop_back();
+			self.producer.shared.poison(&mut self.state);
+			return;
+		}
+		self.state.publish(1);
+		self.producer.shared.not_empty.notify_one();
+	}
+}
+
+/// A slot reserved by `Producer::reserve()`. Dropping it unused frees the
+/// slot again.
+pub struct Permit<T: Send> {
+	shared: Arc<Shared<T>>,
+	used: bool,
+}
+
+impl<T: Send> Permit<T> {
+	/// Puts `value` into the reserved slot. Hands it back if every consumer
+	/// went away in the meantime or the channel was poisoned.
+	pub fn send(mut self, value: T) -> Result<(), SendError<T>> {
+		let mut state = self.shared.lock().unwrap();
+		state.reserved -= 1;
+		self.used = true;
+		if state.consumers == 0 || state.poisoned {
+			self.shared.not_full.notify_all();
+			return Err(SendError(value));
+		}
+		state.push(value);
+		self.shared.not_empty.notify_one();
+		Ok(())
+	}
+}
+
+impl<T: Send> Drop for Permit<T> {
+	fn drop(&mut self) {
+		if !self.used {
+			let mut state = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+			state.reserved -= 1;
+			self.shared.not_full.notify_all();
+		}
+	}
+}
+
+/// Slots reserved by `Producer::write_chunk()`. Like Slot, the queue stays
+/// locked while the chunk is alive.
+pub struct WriteChunk<'a, T: Send + 'a> {
+	producer: &'a Producer<T>,
+	state: MutexGuard<'a, State<T>>,
+	len: usize,
+}
+
+impl<'a, T: Send> WriteChunk<'a, T> {
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	/// The reserved slots in order. The second slice is only non-empty when
+	/// the chunk wraps around the end of the ring.
+	pub fn as_mut_slices(&mut self) -> (&mut [T], &mut [T]) {
+		let len = self.len;
+		let (front, back) = self.state.queue.as_mut_slices();
+		if back.len() >= len {
+			let start = back.len() - len;
+			(&mut back[start..], &mut [])
+		} else {
+			let start = front.len() - (len - back.len());
+			(&mut front[start..], back)
+		}
+	}
+
+	/// Publishes the first `n` slots and gives the rest back.
+	pub fn commit(mut self, n: usize) {
+		let n = n.min(self.len);
+		let keep = self.state.queue.len() - (self.len - n);
+		self.state.queue.truncate(keep);
+		self.len = n;
+	}
+}
+
+impl<'a, T: Send> Drop for WriteChunk<'a, T> {
+	fn drop(&mut self) {
+		if thread::panicking() {
+			let keep = self.state.queue.len() - self.len;
+			self.state.queue.truncate(keep);
+			self.producer.shared.poison(&mut self.state);
+			return;
+		}
+		let len = self.len;
+		self.state.publish(len);
+		if self.len > 0 {
+			self.producer.shared.not_empty.notify_all();
+		}
+	}
+}
+
+impl<T: Send> Clone for Producer<T> {
+	fn clone(&self) -> Self {
+		if let Ok(mut state) = self.shared.lock() {
+			state.producers += 1;
+		}
+		Self { shared: self.shared.clone() }
+	}
+}
+
+impl<T: Send> Drop for Producer<T> {
+	fn drop(&mut self) {
+		let mut state = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+		state.producers -= 1;
+		if thread::panicking() && state.propagate_panics && state.panicked.is_none()
+			&& CATCHING_PANICS.with(|c| c.get()) != self.shared.identity() {
+			// the message is unknown here, Producer::spawn() fills it in
+			let name = thread::current().name().unwrap_or("<unnamed>").to_string();
+			state.panicked = Some(format!("thread '{}' panicked", name));
+			state.notify_watchers();
+			self.shared.not_empty.notify_all();
+		}
+		if state.producers == 0 {
+			// wake up everybody blocked in recv() so they can see the disconnect
+			state.notify_watchers();
+			self.shared.not_empty.notify_all();
+		}
+	}
+}
+
+impl<T: Send> Consumer<T> {
+
+	pub fn new(capacity: usize) -> Self {
+		Self { shared: Shared::new(capacity, None) }
+	}
+
+	// Pops the front value and tells blocked producers about the free slot.
+	fn take(&self, state: &mut State<T>) -> Option<T> {
+		let value = state.pop();
+		if value.is_some() {
+			self.shared.not_full.notify_all();
+		}
+		value
+	}
+
+	fn now(&self) -> Instant {
+		self.shared.lock().unwrap().now()
+	}
+
+	pub fn recv(&self) -> Result<T, RecvError> {
+		// A lot is going on here. self.shared is an Arc of our Shared struct.
+		// Arc can deref into its internal type, so we can call the methods of
+		// that inner type without dereferencing, so this is like
+		//      (*self.shared).lock()
+		// but doesn't look awful. lock() returns a
+		// Result<MutexGuard<State<T>>>.
+		#[cfg(feature = "chaos")]
+		self.shared.chaos_delay();
+
+		let maybe_state = self.shared.lock();
+
+		if let Ok(mut state) = maybe_state {
+
+			// wait on the condvar until there is a value or no producer is
+			// left. Waiting releases the mutex, so producers can make progress.
+			loop {
+				if state.poisoned {
+					return Err(RecvError::Poisoned);
+				}
+				if let Some(value) = self.take(&mut state) {
+					return Ok(value);
+				}
+				if let Some(ref message) = state.panicked {
+					return Err(RecvError::ProducerPanicked(message.clone()));
+				}
+				if state.shut_down() {
+					return Err(RecvError::Shutdown);
+				}
+				if state.producers == 0 {
+					return Err(RecvError::Disconnected);
+				}
+				state.waiting += 1;
+				state = match self.shared.wait(&self.shared.not_empty, state) {
+					Ok(state) => state,
+					Err(_) => return Err(RecvError::Poisoned),
+				};
+				state.waiting -= 1;
+			}
+
+		} else {
+			Err(RecvError::Poisoned)
+		}
+	}
+
+	pub fn try_recv(&self) -> Result<T, TryRecvError> {
+		#[cfg(feature = "chaos")]
+		self.shared.chaos_delay();
+
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Consumer::try_recv() could not lock mutex."),
+		};
+
+		if state.poisoned {
+			return Err(TryRecvError::Poisoned);
+		}
+
+		#[cfg(feature = "chaos")]
+		{
+			if state.chaos(chaos::Fault::Empty) {
+				return Err(TryRecvError::Empty);
+			}
+		}
+
+		match self.take(&mut state) {
+			Some(value) => Ok(value),
+			None if state.disconnected() => Err(TryRecvError::Disconnected),
+			None => Err(TryRecvError::Empty),
+		}
+	}
+
+	pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+		#[cfg(feature = "chaos")]
+		self.shared.chaos_delay();
+
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Consumer::recv_timeout() could not lock mutex."),
+		};
+		let clock = state.clock.clone();
+		let now = || clock.as_ref().map_or_else(Instant::now, |c| c.now());
+		let deadline = now() + timeout;
+
+		loop {
+			if state.poisoned {
+				return Err(RecvTimeoutError::Poisoned);
+			}
+			if let Some(value) = self.take(&mut state) {
+				return Ok(value);
+			}
+			if state.disconnected() {
+				return Err(RecvTimeoutError::Disconnected);
+			}
+			let now = now();
+			if now >= deadline {
+				return Err(RecvTimeoutError::Timeout);
+			}
+			// nobody tells us when a mock clock moves, so look again soon
+			let wait = if clock.is_some() { clock::TICK } else { deadline - now };
+			state.waiting += 1;
+			state = self.shared.wait_timeout(&self.shared.not_empty, state, wait);
+			state.waiting -= 1;
+		}
+	}
+
+	/// Takes the whole backlog at once, without blocking: swaps the queue
+	/// with `buffer`, which has to be empty, and returns how many values it
+	/// holds now. The consumer can go through them without the lock while
+	/// the producers fill the queue again, and hand the emptied buffer back
+	/// on the next call, so neither side allocates in steady state.
+	pub fn swap_buffer(&self, buffer: &mut VecDeque<T>) -> Result<usize, TryRecvError> {
+		assert!(buffer.is_empty(), "Consumer::swap_buffer() buffer is not empty.");
+
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Consumer::swap_buffer() could not lock mutex."),
+		};
+
+		if state.poisoned {
+			return Err(TryRecvError::Poisoned);
+		}
+		if state.queue.is_empty() {
+			return Err(if state.disconnected() { TryRecvError::Disconnected } else { TryRecvError::Empty });
+		}
+
+		let n = state.pop_all(buffer);
+		self.shared.not_full.notify_all();
+		Ok(n)
+	}
+
+	/// Blocks like recv() for the first value, then collects more until
+	/// there are `max_items` or `max_wait` has passed since the first one
+	/// arrived, whichever comes first. A disconnect ends the batch early, it
+	/// is reported by the next call once the queue is empty.
+	pub fn recv_batch(&self, max_items: usize, max_wait: Duration) -> Result<Vec<T>, RecvError> {
+		let mut batch = vec![self.recv()?];
+		let deadline = self.now() + max_wait;
+
+		let mut state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Consumer::recv_batch() could not lock mutex."),
+		};
+		let clock = state.clock.clone();
+		let now = || clock.as_ref().map_or_else(Instant::now, |c| c.now());
+
+		while batch.len() < max_items && !state.poisoned {
+			if let Some(value) = self.take(&mut state) {
+				batch.push(value);
+				continue;
+			}
+			if state.disconnected() {
+				break;
+			}
+			let now = now();
+			if now >= deadline {
+				break;
+			}
+			let wait = if clock.is_some() { clock::TICK } else { deadline - now };
+			state.waiting += 1;
+			state = self.shared.wait_timeout(&self.shared.not_empty, state, wait);
+			state.waiting -= 1;
+		}
+		Ok(batch)
+	}
+
+	pub fn capacity(&self) -> Result<usize, Error> {
+		if let Ok(state) = self.shared.lock() {
+			let capacity = state.queue.capacity();
+			Ok(capacity)
+		} else {
+			panic!("Consumer::capacity() could not lock mutex.");
+		}
+	}
+
+	pub fn size(&self) -> Result<usize, Error> {
+		if let Ok(state) = self.shared.lock() {
+			let len = state.queue.len();
+			Ok(len)
+		} else {
+			panic!("Consumer::size() could not lock mutex.");
+		}
+	}
+
+	/// See `Producer::is_poisoned()`.
+	pub fn is_poisoned(&self) -> bool {
+		self.shared.lock().unwrap().poisoned
+	}
+
+	/// See `Producer::clear_poison()`.
+	pub fn clear_poison(&self) {
+		self.shared.lock().unwrap().poisoned = false;
+	}
+
+	/// Hands everything still queued to `f` when the last consumer of the
+	/// channel is dropped, instead of letting it go down with the channel.
+	/// `f` runs in the thread that drops the last consumer, after the lock
+	/// was released. Producers cannot add anything by then, send() already
+	/// fails. Don't let `f` panic, the drop may be part of unwinding.
+	pub fn on_drop_drain<F: FnMut(T) + Send + 'static>(&self, f: F) {
+		if let Ok(mut state) = self.shared.lock() {
+			state.drain = Some(Box::new(f));
+		}
+	}
+
+	/// Gives access to up to `n` queued values as at most two slices without
+	/// blocking. Dropping the chunk removes all of them from the queue,
+	/// `commit()` only a prefix. Fails on a poisoned channel.
+	pub fn read_chunk(&self, n: usize) -> Result<ReadChunk<'_, T>, Poisoned> {
+		let state = match self.shared.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("Consumer::read_chunk() could not lock mutex."),
+		};
+		if state.poisoned {
+			return Err(Poisoned);
+		}
+
+		#[cfg(feature = "chaos")]
+		let mut state = state;
+		let len = n.min(state.queue.len());
+		#[cfg(feature = "chaos")]
+		let len = if state.chaos(chaos::Fault::Empty) { 0 } else { len };
+		Ok(ReadChunk { consumer: self, state, len })
+	}
+}
+
+/// Values handed out by `Consumer::read_chunk()`. The queue stays locked while
+/// the chunk is alive.
+pub struct ReadChunk<'a, T: Send + 'a> {
+	consumer: &'a Consumer<T>,
+	state: MutexGuard<'a, State<T>>,
+	len: usize,
+}
+
+impl<'a, T: Send> ReadChunk<'a, T> {
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	/// The values in order. The second slice is only non-empty when the
+	/// chunk wraps around the end of the ring.
+	pub fn as_slices(&self) -> (&[T], &[T]) {
+		let (front, back) = self.state.queue.as_slices();
+		if front.len() >= self.len {
+			(&front[..self.len], &[])
+		} else {
+			(front, &back[..self.len - front.len()])
+		}
+	}
+
+	/// Removes only the first `n` values, the rest stay queued.
+	pub fn commit(mut self, n: usize) {
+		self.len = n.min(self.len);
+	}
+}
+
+impl<'a, T: Send> Drop for ReadChunk<'a, T> {
+	fn drop(&mut self) {
+		if thread::panicking() {
+			// the values stay queued
+			self.consumer.shared.poison(&mut self.state);
+			return;
+		}
+		if self.len == 0 {
+			return;
+		}
+		for _ in 0..self.len {
+			self.state.pop();
+		}
+		self.consumer.shared.not_full.notify_all();
+	}
+}
+
+impl<T: Send> Clone for Consumer<T> {
+	fn clone(&self) -> Self {
+		if let Ok(mut state) = self.shared.lock() {
+			state.consumers += 1;
+		}
+		Self { shared: self.shared.clone() }
+	}
+}
+
+impl<T: Send> Drop for Consumer<T> {
+	fn drop(&mut self) {
+		let mut state = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+		state.consumers -= 1;
+		if state.consumers == 0 {
+			// wake up blocked producers so send() can hand the value back
+			state.notify_watchers();
+			self.shared.not_full.notify_all();
+
+			if let Some(mut drain) = state.drain.take() {
+				let mut left = Vec::with_capacity(state.queue.len());
+				while let Some(value) = state.pop() {
+					left.push(value);
+				}
+				drop(state);
+				for value in left {
+					drain(value);
+				}
+			}
+		}
+	}
+}
+
+/// Creates an unbounded channel. `capacity` only preallocates the queue.
+pub fn channel<T: Send>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+
+	let shared = Shared::new(capacity, None);
+
+	(
+		Producer {
+			shared: shared.clone(),
+		},
+		Consumer {
+			shared,
+		}
+	)
+}
+
+/// Creates a bounded channel where send() blocks while `bound` values are
+/// queued. Blocked producers get the free slots in the order they arrived.
+/// With a bound of zero every send() blocks until it was received.
+pub fn sync_channel<T: Send>(bound: usize) -> (Producer<T>, Consumer<T>) {
+
+	let shared = Shared::new(bound, Some(bound));
+
+	(
+		Producer {
+			shared: shared.clone(),
+		},
+		Consumer {
+			shared,
+		}
+	)
+}
+
+
+/*
+ * Tests.
+ */
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::thread;
+
+	#[test]
+	fn test_consumer_pop() {
+		let capacity: usize = 100;
+		let (px, cx) = channel(capacity);
+
+		// VecDeque used to round its capacity up to the next power of two
+		// (minus one), newer versions hand out at least what was asked for.
+		for i in 0..9 {
+			px.send(i).unwrap();
+			assert!(px.capacity().unwrap() >= capacity);
+			assert_eq!(px.size().unwrap(), i+1);
+		}
+
+		for i in 0..9 {
+			assert_eq!(cx.size().unwrap(), 9-i);
+			let t = cx.recv().unwrap();
+			assert!(cx.capacity().unwrap() >= capacity);
+			assert_eq!(cx.size().unwrap(), 9-i-1);
+			assert_eq!(t, i);
+		}
+	}
+
+	#[test]
+	fn queue_length_is_accurate() {
+		let (px, cx) = channel(100);
+		assert_eq!(0, cx.size().unwrap());
+		for i in 0..11 {
+			px.send(i).unwrap();
+			assert_eq!(i+1, cx.size().unwrap());
+		}
+	}
+
+	#[test]
+	fn threaded_queue_multiple_producer_single_consumer() {
+		let (px1, cx) = channel(100);
+		let px2 = px1.clone();
+
+		thread::spawn(move || {
+			px1.send(1).unwrap();
+		});
+
+		thread::spawn(move|| {
+			px2.send(1).unwrap();
+		});
+
+		for _ in 0 .. 1 {
+			assert_eq!(1, cx.recv().unwrap());
+		}
+	}
+
+	#[test]
+	fn test_threaded() {
+		let capacity: usize = 64;
+		let (px, cx) = channel(capacity);
+
+		let producer_thread = thread::spawn(move || {
+			for i in 0..1000 {
+				px.send(i).unwrap();
+			}
+		});
+
+		let consumer_thread = thread::spawn(move || {
+			for i in 0..1000 {
+				match cx.recv() {
+					Ok(val)  => {
+						assert_eq!(val, i);
+					},
+					Err(e) => {
+						println!("Error: {:?}", e)
+					},
+				};
+			}
+		});
+
+		producer_thread.join().unwrap();
+		consumer_thread.join().unwrap();
+	}
+
+	#[test]
+	fn recv_fails_after_producers_are_gone() {
+		let (px, cx) = channel(4);
+		let px2 = px.clone();
+		px.send(1).unwrap();
+		drop(px);
+		drop(px2);
+
+		// what was queued before the disconnect is still delivered
+		assert_eq!(cx.recv().unwrap(), 1);
+		assert!(cx.recv().is_err());
+		assert_eq!(cx.try_recv(), Err(TryRecvError::Disconnected));
+	}
+
+	#[test]
+	fn send_fails_after_consumers_are_gone() {
+		let (px, cx) = channel(4);
+		drop(cx);
+		match px.send(7) {
+			Err(SendError(value)) => assert_eq!(value, 7),
+			Ok(()) => panic!("send() succeeded without a consumer"),
+		}
+	}
+
+	#[test]
+	fn try_recv_and_recv_timeout_on_empty_channel() {
+		let (px, cx) = channel::<u32>(4);
+		assert_eq!(cx.try_recv(), Err(TryRecvError::Empty));
+		assert_eq!(cx.recv_timeout(Duration::from_millis(10)), Err(RecvTimeoutError::Timeout));
+		px.send(3).unwrap();
+		assert_eq!(cx.recv_timeout(Duration::from_millis(10)), Ok(3));
+	}
+
+	#[test]
+	fn sync_channel_blocks_when_full() {
+		let (px, cx) = sync_channel(2);
+		px.send(1).unwrap();
+		px.send(2).unwrap();
+		assert_eq!(px.try_send(3), Err(TrySendError::Full(3)));
+
+		let producer_thread = thread::spawn(move || {
+			// blocks until the consumer made room
+			px.send(3).unwrap();
+		});
+
+		assert_eq!(cx.recv().unwrap(), 1);
+		producer_thread.join().unwrap();
+		assert_eq!(cx.recv().unwrap(), 2);
+		assert_eq!(cx.recv().unwrap(), 3);
+	}
+
+	#[test]
+	fn swap_buffer_takes_the_backlog_and_frees_the_queue() {
+		let (px, cx) = sync_channel(3);
+		for i in 0..3 {
+			px.send(i).unwrap();
+		}
+		let mut buffer = VecDeque::with_capacity(3);
+		assert_eq!(cx.swap_buffer(&mut buffer), Ok(3));
+		assert_eq!(buffer, vec![0, 1, 2]);
+
+		// the producer has the whole bound again
+		for i in 3..6 {
+			assert_eq!(px.try_send(i), Ok(()));
+		}
+		buffer.clear();
+		assert_eq!(cx.swap_buffer(&mut buffer), Ok(3));
+		assert_eq!(buffer, vec![3, 4, 5]);
+
+		buffer.clear();
+		assert_eq!(cx.swap_buffer(&mut buffer), Err(TryRecvError::Empty));
+		drop(px);
+		assert_eq!(cx.swap_buffer(&mut buffer), Err(TryRecvError::Disconnected));
+	}
+
+	#[test]
+	fn recv_batch_stops_at_max_items_or_max_wait() {
+		let (px, cx) = channel(0);
+		let clock = clock::MockClock::new();
+		clock::use_clock(&px, clock.clone());
+		for i in 0..5 {
+			px.send(i).unwrap();
+		}
+		assert_eq!(cx.recv_batch(3, Duration::from_secs(1)), Ok(vec![0, 1, 2]));
+
+		let consumer_thread = thread::spawn(move || cx.recv_batch(10, Duration::from_secs(1)));
+		thread::sleep(Duration::from_millis(20));
+		px.send(5).unwrap();
+		while !consumer_thread.is_finished() {
+			clock.advance(Duration::from_millis(100));
+			thread::sleep(Duration::from_millis(1));
+		}
+		assert_eq!(consumer_thread.join().unwrap(), Ok(vec![3, 4, 5]));
+	}
+
+	#[test]
+	fn recv_batch_reports_the_disconnect_after_the_last_batch() {
+		let (px, cx) = channel(0);
+		px.send(1).unwrap();
+		drop(px);
+		assert_eq!(cx.recv_batch(10, Duration::from_secs(60)), Ok(vec![1]));
+		assert_eq!(cx.recv_batch(10, Duration::from_secs(60)), Err(RecvError::Disconnected));
+	}
+
+	#[test]
+	fn permits_hold_their_slot() {
+		let (px, cx) = sync_channel(2);
+		let permit = px.reserve().unwrap();
+		px.send(1).unwrap();
+		assert_eq!(px.try_send(2), Err(TrySendError::Full(2)));
+
+		let unused = thread::spawn({
+			let px = px.clone();
+			// blocks until the value or the permit is gone
+			move || drop(px.reserve().unwrap())
+		});
+		permit.send(3).unwrap();
+		assert_eq!(cx.recv(), Ok(1));
+		unused.join().unwrap();
+		assert_eq!(cx.recv(), Ok(3));
+
+		let permit = px.reserve().unwrap();
+		drop(cx);
+		assert_eq!(permit.send(4).err().unwrap().0, 4);
+		assert!(px.reserve().is_err());
+	}
+
+	#[test]
+	fn blocked_producers_are_served_in_arrival_order() {
+		let (px, cx) = sync_channel(1);
+		px.send(0).unwrap();
+
+		let early = px.clone();
+		let early = thread::spawn(move || early.send(1).unwrap());
+		thread::sleep(Duration::from_millis(50));
+
+		// sends in a tight loop, without FIFO handoff it could keep winning
+		// the free slot against the producer that waited longer
+		let eager = px.clone();
+		let eager = thread::spawn(move || for i in 100..105 { eager.send(i).unwrap(); });
+		thread::sleep(Duration::from_millis(50));
+		assert_eq!(px.try_send(7), Err(TrySendError::Full(7)));
+		drop(px);
+
+		let received: Vec<_> = (0..7).map(|_| cx.recv().unwrap()).collect();
+		assert_eq!(received, vec![0, 1, 100, 101, 102, 103, 104]);
+		early.join().unwrap();
+		eager.join().unwrap();
+	}
+
+	#[test]
+	fn claim_waits_its_turn_behind_blocked_senders() {
+		let (px, cx) = sync_channel(1);
+		px.send(0u32).unwrap();
+
+		let sender = px.clone();
+		let sender = thread::spawn(move || sender.send(1).unwrap());
+		thread::sleep(Duration::from_millis(50));
+		let claimer = px.clone();
+		let claimer = thread::spawn(move || *claimer.claim().unwrap() = 2);
+		thread::sleep(Duration::from_millis(50));
+		let late = px.clone();
+		let late = thread::spawn(move || late.send(3).unwrap());
+		thread::sleep(Duration::from_millis(50));
+		drop(px);
+
+		let received: Vec<_> = (0..4).map(|_| cx.recv().unwrap()).collect();
+		assert_eq!(received, vec![0, 1, 2, 3]);
+		sender.join().unwrap();
+		claimer.join().unwrap();
+		late.join().unwrap();
+	}
+
+	#[test]
+	fn rendezvous_send_waits_for_recv() {
+		let (px, cx) = sync_channel(0);
+		assert_eq!(px.try_send(1), Err(TrySendError::Full(1)));
+
+		let producer_thread = thread::spawn(move || {
+			px.send(5).unwrap();
+		});
+
+		assert_eq!(cx.recv().unwrap(), 5);
+		producer_thread.join().unwrap();
+	}
+
+	#[derive(Clone, Copy)]
+	struct Frame {
+		id: u32,
+		payload: [u8; 256],
+	}
+
+	impl Default for Frame {
+		fn default() -> Self {
+			Frame { id: 0, payload: [0; 256] }
+		}
+	}
+
+	#[test]
+	fn claim_publishes_on_drop() {
+		let (px, cx) = channel::<Frame>(4);
+		{
+			let mut slot = px.claim().unwrap();
+			slot.id = 42;
+			for (i, b) in slot.payload.iter_mut().enumerate() {
+				*b = i as u8;
+			}
+		}
+		let frame = cx.recv().unwrap();
+		assert_eq!(frame.id, 42);
+		assert_eq!(frame.payload[255], 255);
+	}
+
+	#[test]
+	fn claim_waits_for_room_on_bounded_channel() {
+		let (px, cx) = sync_channel(1);
+		px.send(1u32).unwrap();
+
+		let producer_thread = thread::spawn(move || {
+			*px.claim().unwrap() = 2;
+		});
+
+		assert_eq!(cx.recv().unwrap(), 1);
+		assert_eq!(cx.recv().unwrap(), 2);
+		producer_thread.join().unwrap();
+	}
+
+	#[test]
+	fn panic_inside_claim_poisons_the_channel() {
+		let (px, cx) = channel::<u32>(4);
+		let consumer_thread = thread::spawn(move || (cx.recv(), cx));
+		thread::sleep(Duration::from_millis(10));
+
+		let px2 = px.clone();
+		let producer_thread = thread::spawn(move || {
+			let mut slot = px2.claim().unwrap();
+			*slot = 13;
+			panic!("half way through");
+		});
+		assert!(producer_thread.join().is_err());
+
+		// the blocked consumer was woken up
+		let (result, cx) = consumer_thread.join().unwrap();
+		assert_eq!(result, Err(RecvError::Poisoned));
+		assert!(px.is_poisoned());
+		assert_eq!(px.try_send(1), Err(TrySendError::Poisoned(1)));
+		assert!(px.send(1).is_err());
+		assert_eq!(cx.try_recv(), Err(TryRecvError::Poisoned));
+		assert_eq!(px.claim().err(), Some(Poisoned));
+		assert_eq!(px.write_chunk(1).err(), Some(Poisoned));
+		assert_eq!(cx.read_chunk(1).err(), Some(Poisoned));
+
+		cx.clear_poison();
+		assert!(!px.is_poisoned());
+		assert_eq!(cx.try_recv(), Err(TryRecvError::Empty));
+		px.send(2).unwrap();
+		assert_eq!(cx.recv(), Ok(2));
+	}
+
+	#[test]
+	fn panic_inside_read_chunk_keeps_the_values() {
+		let (px, cx) = channel::<u32>(4);
+		px.send(1).unwrap();
+		px.send(2).unwrap();
+
+		let cx = thread::spawn(move || {
+			let result = panic::catch_unwind(AssertUnwindSafe(|| {
+				let _chunk = cx.read_chunk(2).unwrap();
+				panic!("could not process");
+			}));
+			assert!(result.is_err());
+			cx
+		}).join().unwrap();
+
+		assert_eq!(cx.recv(), Err(RecvError::Poisoned));
+		cx.clear_poison();
+		assert_eq!(cx.recv(), Ok(1));
+		assert_eq!(cx.recv(), Ok(2));
+	}
+
+	#[test]
+	fn write_chunk_and_read_chunk_across_the_wrap_point() {
+		let (px, cx) = sync_channel::<u32>(8);
+
+		// move the head of the ring forward so the next chunk has to wrap
+		for i in 0..6 {
+			px.send(i).unwrap();
+		}
+		for _ in 0..6 {
+			cx.recv().unwrap();
+		}
+
+		{
+			let mut chunk = px.write_chunk(10).unwrap();
+			assert_eq!(chunk.len(), 8);
+			let (a, b) = chunk.as_mut_slices();
+			for (i, slot) in a.iter_mut().chain(b.iter_mut()).enumerate() {
+				*slot = i as u32;
+			}
+			chunk.commit(5);
+		}
+		assert_eq!(cx.size().unwrap(), 5);
+		assert_eq!(px.write_chunk(4).unwrap().len(), 3);
+		assert_eq!(cx.size().unwrap(), 8);
+
+		let chunk = cx.read_chunk(6).unwrap();
+		let (a, b) = chunk.as_slices();
+		let values: Vec<u32> = a.iter().chain(b.iter()).cloned().collect();
+		assert_eq!(values, vec![0, 1, 2, 3, 4, 0]);
+		chunk.commit(4);
+		assert_eq!(cx.size().unwrap(), 4);
+		assert_eq!(cx.recv().unwrap(), 4);
+	}
+
+	#[test]
+	fn producer_panic_reaches_the_consumer() {
+		let (px, cx) = channel::<u32>(4);
+		let keep = px.clone();
+
+		let producer_thread = px.spawn(|px| {
+			px.send(1).unwrap();
+			panic!("sensor {} on fire", 3);
+		});
+
+		assert_eq!(cx.recv(), Ok(1));
+		// another producer is still alive, the consumer would hang without this
+		assert_eq!(cx.recv(), Err(RecvError::ProducerPanicked("sensor 3 on fire".to_string())));
+		assert_eq!(cx.try_recv(), Err(TryRecvError::Disconnected));
+		assert!(producer_thread.join().is_err());
+		drop(keep);
+	}
+
+	#[test]
+	fn propagate_panics_without_spawn() {
+		let (px, cx) = channel::<u32>(4);
+		px.propagate_panics();
+
+		let producer_thread = thread::Builder::new().name("sensor".to_string()).spawn(move || {
+			px.send(1).unwrap();
+			panic!("boom");
+		}).unwrap();
+
+		assert!(producer_thread.join().is_err());
+		assert_eq!(cx.recv(), Ok(1));
+		assert_eq!(cx.recv(), Err(RecvError::ProducerPanicked("thread 'sensor' panicked".to_string())));
+	}
+
+	#[test]
+	fn spawn_only_reports_for_its_own_channel() {
+		let (px, cx) = channel::<u32>(4);
+		let (other_px, other_cx) = channel::<u32>(4);
+		other_px.propagate_panics();
+
+		let producer_thread = px.spawn(move |px| {
+			// unwinds together with the spawned one, on the same thread
+			let other_px = other_px;
+			other_px.send(1).unwrap();
+			px.send(2).unwrap();
+			panic!("boom");
+		});
+
+		assert!(producer_thread.join().is_err());
+		assert_eq!(cx.recv(), Ok(2));
+		assert_eq!(cx.recv(), Err(RecvError::ProducerPanicked("boom".to_string())));
+		assert_eq!(other_cx.recv(), Ok(1));
+		assert_eq!(other_cx.recv(), Err(RecvError::ProducerPanicked("thread '<unnamed>' panicked".to_string())));
+	}
+
+	#[test]
+	fn last_consumer_drains_into_the_callback() {
+		let (px, cx) = channel::<u32>(8);
+		let left = Arc::new(Mutex::new(Vec::new()));
+		let sink = left.clone();
+		cx.on_drop_drain(move |v| sink.lock().unwrap().push(v));
+
+		for i in 0..5 {
+			px.send(i).unwrap();
+		}
+		assert_eq!(cx.recv().unwrap(), 0);
+
+		let cx2 = cx.clone();
+		drop(cx);
+		assert!(left.lock().unwrap().is_empty());
+		drop(cx2);
+		assert_eq!(*left.lock().unwrap(), [1, 2, 3, 4]);
+		assert!(px.send(5).is_err());
+	}
+
+	extern crate time;
+	use self::time::PreciseTime;
+
+
+	#[test]
+	#[ignore]
+	fn bench_spsc_throughput() {
+		let iterations: i64 = 2i64.pow(20);
+
+		let (px, cx) = channel(512);
+
+		let start = PreciseTime::now();
+		for i in 0..iterations as usize {
+			px.send(i).unwrap();
+		}
+		let t = cx.recv().unwrap();
+		assert_eq!(t, 0);
+		let end = PreciseTime::now();
+		let throughput =
+			(iterations as f64 / (start.to(end)).num_nanoseconds().unwrap() as f64) * 1000000000f64;
+		println!(
+			"Spsc Throughput: {:.2}/s -- (iterations: {} in {} ns)",
+			throughput,
+			iterations,
+			(start.to(end)).num_nanoseconds().unwrap()
+		);
+	}
+
+	// we can either take the normal streaming channel mpsc::channel
+	// or the mpsc::sync_channel
+	// see here: https://doc.rust-lang.org/std/sync/mpsc/
+	use std::sync::mpsc::channel as mpsc_channel;
+
+	#[test]
+	#[ignore]
+	fn bench_mpsc_stdlib_throughput() {
+		let iterations: i64 = 2i64.pow(20);
+
+		let (tx, rx) = mpsc_channel();
+
+		let start = PreciseTime::now();
+		for i in 0..iterations as usize {
+			tx.send(i).unwrap();
+		}
+		let t = rx.recv().unwrap();
+		assert_eq!(t, 0);
+		let end = PreciseTime::now();
+		let throughput =
+			(iterations as f64 / (start.to(end)).num_nanoseconds().unwrap() as f64) * 1000000000f64;
+		println!(
+			"MPSC Stdlib Throughput: {:.2}/s -- (iterations: {} in {} ns)",
+			throughput,
+			iterations,
+			(start.to(end)).num_nanoseconds().unwrap()
+		);
+	}
+
+}
@@ -0,0 +1,144 @@
+//! Recycling large message buffers. Producers take buffers from a `Pool`
+//! instead of allocating them, and consumers hand them back through a
+//! `Recycler` once they are done with the message. The way back is a channel
+//! of its own, so in steady state the same few buffers go around and nothing
+//! is allocated per message.
+//!
+//! The pool keeps at most `max_spare` buffers. Buffers recycled beyond that
+//! are dropped, and `get()` on an empty pool makes a new one, so the pool
+//! never blocks either side.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use super::{sync_channel, Consumer, Producer};
+
+/// Clears a buffer for its next use, keeping its allocation.
+pub trait Recycle {
+	fn reset(&mut self);
+}
+
+impl<T> Recycle for Vec<T> {
+	fn reset(&mut self) {
+		self.clear();
+	}
+}
+
+impl<T> Recycle for VecDeque<T> {
+	fn reset(&mut self) {
+		self.clear();
+	}
+}
+
+impl Recycle for String {
+	fn reset(&mut self) {
+		self.clear();
+	}
+}
+
+/// Where producers get their buffers, can be cloned.
+pub struct Pool<T: Send> {
+	spare: Consumer<T>,
+	make: Arc<dyn Fn() -> T + Send + Sync>,
+}
+
+/// How consumers give buffers back, can be cloned.
+pub struct Recycler<T: Send> {
+	returns: Producer<T>,
+}
+
+/// Creates a pool keeping up to `max_spare` buffers, and making new ones
+/// with `make`.
+pub fn pool<T, F>(max_spare: usize, make: F) -> (Pool<T>, Recycler<T>)
+	where T: Recycle + Send, F: Fn() -> T + Send + Sync + 'static
+{
+	let (returns, spare) = sync_channel(max_spare.max(1));
+	(Pool { spare, make: Arc::new(make) }, Recycler { returns })
+}
+
+impl<T: Send> Pool<T> {
+	/// A recycled buffer if there is one, a new one otherwise.
+	pub fn get(&self) -> T {
+		match self.spare.try_recv() {
+			Ok(buffer) => buffer,
+			Err(_) => (self.make)(),
+		}
+	}
+
+	/// Number of buffers waiting to be reused.
+	pub fn spare(&self) -> usize {
+		self.spare.size().unwrap()
+	}
+}
+
+impl<T: Send> Clone for Pool<T> {
+	fn clone(&self) -> Self {
+		Pool { spare: self.spare.clone(), make: self.make.clone() }
+	}
+}
+
+impl<T: Recycle + Send> Recycler<T> {
+	/// Resets `buffer` and gives it back to the pool, or drops it if the
+	/// pool is full or gone.
+	pub fn recycle(&self, mut buffer: T) {
+		buffer.reset();
+		let _ = self.returns.try_send(buffer);
+	}
+}
+
+impl<T: Send> Clone for Recycler<T> {
+	fn clone(&self) -> Self {
+		Recycler { returns: self.returns.clone() }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::channel;
+	use std::thread;
+
+	#[test]
+	fn buffers_go_around() {
+		let (pool, recycler) = pool(4, || Vec::<u8>::with_capacity(1024));
+		let (px, cx) = channel::<Vec<u8>>(0);
+
+		let consumer_thread = thread::spawn(move || {
+			let mut addresses = Vec::new();
+			while let Ok(buffer) = cx.recv() {
+				assert_eq!(buffer.len(), 100);
+				addresses.push(buffer.as_ptr() as usize);
+				recycler.recycle(buffer);
+			}
+			addresses
+		});
+
+		for _ in 0..100 {
+			let mut buffer = pool.get();
+			assert!(buffer.is_empty());
+			buffer.extend_from_slice(&[7; 100]);
+			px.send(buffer).unwrap();
+			// wait for the buffer to come back, so the next get() reuses it
+			while pool.spare() == 0 && !consumer_thread.is_finished() {
+				thread::yield_now();
+			}
+		}
+		drop(px);
+
+		let mut addresses = consumer_thread.join().unwrap();
+		addresses.sort();
+		addresses.dedup();
+		assert_eq!(addresses.len(), 1, "{} buffers allocated", addresses.len());
+	}
+
+	#[test]
+	fn extra_buffers_are_dropped() {
+		let (pool, recycler) = pool(2, String::new);
+		for _ in 0..5 {
+			recycler.recycle("spare".to_string());
+		}
+		assert_eq!(pool.spare(), 2);
+		assert_eq!(pool.get(), "");
+	}
+}
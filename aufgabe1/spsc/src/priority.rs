@@ -0,0 +1,234 @@
+//! A channel with three fixed priority classes. Every class has a ring of its
+//! own, and `recv()` always takes from the highest class that has anything,
+//! so a High message never waits behind Normal or Low traffic. Within a class
+//! messages keep their order.
+//!
+//! This is strict priority: as long as High messages keep coming, Normal and
+//! Low ones are not received at all. There is no heap, sending and receiving
+//! are a push and a pop on one of the rings.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+
+use super::{RecvError, SendError, TryRecvError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+	High,
+	Normal,
+	Low,
+}
+
+const LANES: usize = 3;
+
+struct State<T> {
+	// indexed by Priority, High first
+	lanes: [VecDeque<T>; LANES],
+	// per lane, None means unbounded
+	bound: Option<usize>,
+	producers: usize,
+	consumer: bool,
+}
+
+impl<T> State<T> {
+	fn pop(&mut self) -> Option<T> {
+		self.lanes.iter_mut().filter_map(|lane| lane.pop_front()).next()
+	}
+}
+
+struct Shared<T> {
+	state: Mutex<State<T>>,
+	// signalled when a message is sent or the last producer goes away
+	not_empty: Condvar,
+	// signalled when a message is taken or the consumer goes away
+	not_full: Condvar,
+}
+
+pub struct PriorityProducer<T: Send> {
+	shared: Arc<Shared<T>>,
+}
+
+pub struct PriorityConsumer<T: Send> {
+	shared: Arc<Shared<T>>,
+}
+
+/// Creates an unbounded priority channel, `send()` never blocks.
+pub fn priority_channel<T: Send>() -> (PriorityProducer<T>, PriorityConsumer<T>) {
+	new_channel(None)
+}
+
+/// Creates a priority channel where each class holds up to `bound` messages
+/// before `send()` blocks for it. Panics if `bound` is zero, unlike
+/// `sync_channel()` there is no rendezvous mode.
+pub fn sync_priority_channel<T: Send>(bound: usize) -> (PriorityProducer<T>, PriorityConsumer<T>) {
+	assert!(bound > 0, "sync_priority_channel() bound must not be zero.");
+	new_channel(Some(bound))
+}
+
+fn new_channel<T: Send>(bound: Option<usize>) -> (PriorityProducer<T>, PriorityConsumer<T>) {
+	let shared = Arc::new(Shared {
+		state: Mutex::new(State {
+			lanes: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+			bound,
+			producers: 1,
+			consumer: true,
+		}),
+		not_empty: Condvar::new(),
+		not_full: Condvar::new(),
+	});
+
+	(PriorityProducer { shared: shared.clone() }, PriorityConsumer { shared })
+}
+
+impl<T: Send> PriorityProducer<T> {
+
+	/// Queues `value` in the class `priority`, blocking while that class is
+	/// full. Fails if the consumer is gone.
+	pub fn send(&self, priority: Priority, value: T) -> Result<(), SendError<T>> {
+		let mut state = match self.shared.state.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("PriorityProducer::send() could not lock mutex."),
+		};
+
+		let lane = priority as usize;
+		while state.consumer && state.bound.is_some_and(|bound| state.lanes[lane].len() >= bound) {
+			state = self.shared.not_full.wait(state).unwrap();
+		}
+
+		if !state.consumer {
+			return Err(SendError(value));
+		}
+
+		state.lanes[lane].push_back(value);
+		self.shared.not_empty.notify_one();
+		Ok(())
+	}
+
+	/// Number of messages waiting in the class `priority`.
+	pub fn len(&self, priority: Priority) -> usize {
+		self.shared.state.lock().unwrap().lanes[priority as usize].len()
+	}
+}
+
+impl<T: Send> Clone for PriorityProducer<T> {
+	fn clone(&self) -> Self {
+		self.shared.state.lock().unwrap().producers += 1;
+		PriorityProducer { shared: self.shared.clone() }
+	}
+}
+
+impl<T: Send> Drop for PriorityProducer<T> {
+	fn drop(&mut self) {
+		let mut state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+		state.producers -= 1;
+		if state.producers == 0 {
+			self.shared.not_empty.notify_all();
+		}
+	}
+}
+
+impl<T: Send> PriorityConsumer<T> {
+
+	/// Returns the oldest message of the highest class that has one,
+	/// blocking while all classes are empty. Fails once every producer is
+	/// gone and everything was received.
+	pub fn recv(&self) -> Result<T, RecvError> {
+		let mut state = match self.shared.state.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("PriorityConsumer::recv() could not lock mutex."),
+		};
+
+		loop {
+			if let Some(value) = state.pop() {
+				self.shared.not_full.notify_all();
+				return Ok(value);
+			}
+			if state.producers == 0 {
+				return Err(RecvError::Disconnected);
+			}
+			state = self.shared.not_empty.wait(state).unwrap();
+		}
+	}
+
+	pub fn try_recv(&self) -> Result<T, TryRecvError> {
+		let mut state = match self.shared.state.lock() {
+			Ok(state) => state,
+			Err(_) => panic!("PriorityConsumer::try_recv() could not lock mutex."),
+		};
+
+		match state.pop() {
+			Some(value) => {
+				self.shared.not_full.notify_all();
+				Ok(value)
+			}
+			None if state.producers == 0 => Err(TryRecvError::Disconnected),
+			None => Err(TryRecvError::Empty),
+		}
+	}
+
+	/// Number of messages waiting in the class `priority`.
+	pub fn len(&self, priority: Priority) -> usize {
+		self.shared.state.lock().unwrap().lanes[priority as usize].len()
+	}
+}
+
+impl<T: Send> Drop for PriorityConsumer<T> {
+	fn drop(&mut self) {
+		let mut state = self.shared.state.lock().unwrap_or_else(|e| e.into_inner());
+		state.consumer = false;
+		self.shared.not_full.notify_all();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::panic;
+	use std::thread;
+
+	#[test]
+	fn higher_classes_go_first() {
+		let (px, cx) = priority_channel();
+		px.send(Priority::Low, "low 1").unwrap();
+		px.send(Priority::Normal, "normal").unwrap();
+		px.send(Priority::Low, "low 2").unwrap();
+		px.send(Priority::High, "high").unwrap();
+		drop(px);
+
+		let order: Vec<_> = (0..4).map(|_| cx.recv().unwrap()).collect();
+		assert_eq!(order, vec!["high", "normal", "low 1", "low 2"]);
+		assert_eq!(cx.recv(), Err(RecvError::Disconnected));
+	}
+
+	#[test]
+	fn a_full_class_does_not_block_the_others() {
+		let (px, cx) = sync_priority_channel(1);
+		px.send(Priority::Low, 1).unwrap();
+		px.send(Priority::High, 2).unwrap();
+
+		let sender = thread::spawn(move || {
+			// blocks until the consumer took the first Low message
+			px.send(Priority::Low, 3).unwrap();
+		});
+
+		assert_eq!(cx.recv(), Ok(2));
+		assert_eq!(cx.recv(), Ok(1));
+		assert_eq!(cx.recv(), Ok(3));
+		sender.join().unwrap();
+		assert_eq!(cx.try_recv(), Err(TryRecvError::Disconnected));
+	}
+
+	#[test]
+	fn send_fails_without_consumer() {
+		let (px, cx) = priority_channel::<u32>();
+		drop(cx);
+		assert!(px.send(Priority::Normal, 1).is_err());
+		assert_eq!(px.len(Priority::Normal), 0);
+	}
+
+	#[test]
+	fn zero_bound_is_rejected() {
+		assert!(panic::catch_unwind(|| sync_priority_channel::<u32>(0)).is_err());
+	}
+}
@@ -0,0 +1,341 @@
+//! Record and replay for chasing ordering bugs.
+//!
+//! A recording channel writes one line per operation to a log while holding
+//! the channel lock, so the order in the log is exactly the order in which
+//! the operations took effect:
+//!
+//! ```text
+//! <seq> TAB <thread> TAB send|recv TAB <value>
+//! ```
+//!
+//! `replay_channel()` reads such a log back and hands out a producer and a
+//! consumer that make every operation wait until it is its thread's turn
+//! according to the log. Running the same program against them reproduces
+//! the recorded interleaving every time. If the program does something the
+//! log does not expect, the replay panics and says where it diverged.
+//!
+//! Threads are told apart by name, so give the threads that use the channel
+//! names with `thread::Builder::name()`, both when recording and replaying.
+//! Values are written with their Debug representation.
+
+use std::fmt::Debug;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Consumer, Event, Producer, RecvError, SendError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+	Send,
+	Recv,
+}
+
+/// One line of the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+	pub seq: u64,
+	pub thread: String,
+	pub op: Op,
+	pub value: String,
+}
+
+/// Like `spsc::channel()`, but every operation is written to `log`. Write
+/// errors are ignored so a full disk does not take the channel down.
+pub fn channel<T, W>(capacity: usize, log: W) -> (Producer<T>, Consumer<T>)
+	where T: Send + Debug, W: Write + Send + 'static
+{
+	let (px, cx) = super::channel(capacity);
+	attach(&px, log);
+	(px, cx)
+}
+
+/// Like `spsc::sync_channel()`, but every operation is written to `log`.
+pub fn sync_channel<T, W>(bound: usize, log: W) -> (Producer<T>, Consumer<T>)
+	where T: Send + Debug, W: Write + Send + 'static
+{
+	let (px, cx) = super::sync_channel(bound);
+	attach(&px, log);
+	(px, cx)
+}
+
+fn attach<T, W>(px: &Producer<T>, mut log: W)
+	where T: Send + Debug, W: Write + Send + 'static
+{
+	let mut seq = 0u64;
+	px.shared.lock().unwrap().hook = Some(Box::new(move |event, value| {
+		let op = match event {
+			Event::Sent => "send",
+			Event::Received => "recv",
+		};
+		// format first, so a panicking Debug impl cannot leave half a line
+		let line = format!("{}\t{}\t{}\t{:?}\n", seq, thread_name(), op, value);
+		let _ = log.write_all(line.as_bytes());
+		seq += 1;
+	}));
+}
+
+/// If recording stopped because writing an entry panicked (the Debug impl of
+/// a value, or the writer), the panic message. The channel itself keeps
+/// working, only the log is incomplete from that point on.
+pub fn failure<T: Send>(px: &Producer<T>) -> Option<String> {
+	px.shared.lock().unwrap().hook_failure.clone()
+}
+
+fn thread_name() -> String {
+	let current = thread::current();
+	match current.name() {
+		Some(name) => name.to_string(),
+		None => format!("{:?}", current.id()),
+	}
+}
+
+/// Parses a log written by a recording channel.
+pub fn load<R: BufRead>(log: R) -> io::Result<Vec<Entry>> {
+	let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad log line: {:?}", line));
+
+	let mut entries = Vec::new();
+	for line in log.lines() {
+		let line = line?;
+		if line.is_empty() {
+			continue;
+		}
+		let fields: Vec<&str> = line.splitn(4, '\t').collect();
+		if fields.len() != 4 {
+			return Err(invalid(&line));
+		}
+		let op = match fields[2] {
+			"send" => Op::Send,
+			"recv" => Op::Recv,
+			_ => return Err(invalid(&line)),
+		};
+		entries.push(Entry {
+			seq: fields[0].parse().map_err(|_| invalid(&line))?,
+			thread: fields[1].to_string(),
+			op,
+			value: fields[3].to_string(),
+		});
+	}
+	Ok(entries)
+}
+
+struct Script {
+	entries: Vec<Entry>,
+	next: Mutex<usize>,
+	advanced: Condvar,
+	// how long a thread waits for its turn before the replay counts as stuck
+	patience: Duration,
+}
+
+impl Script {
+	// Blocks until the next entry belongs to the calling thread. Returns false
+	// once the log is used up, from then on everything runs unchecked.
+	fn wait_turn(&self, op: Op, value: Option<&str>) -> bool {
+		let me = thread_name();
+		let deadline = Instant::now() + self.patience;
+		let mut next = self.next.lock().unwrap();
+
+		loop {
+			let entry = match self.entries.get(*next) {
+				Some(entry) => entry,
+				None => return false,
+			};
+
+			if entry.thread == me {
+				if entry.op != op || value.is_some_and(|v| v != entry.value) {
+					panic!("replay diverged at seq {}: {} does {:?} {:?}, the log says {:?} {:?}",
+						entry.seq, me, op, value.unwrap_or("_"), entry.op, entry.value);
+				}
+				return true;
+			}
+
+			let now = Instant::now();
+			if now >= deadline {
+				panic!("replay stuck at seq {}: {} wants to {:?}, the log expects {} to {:?} {:?}",
+					entry.seq, me, op, entry.thread, entry.op, entry.value);
+			}
+			next = self.advanced.wait_timeout(next, deadline - now).unwrap().0;
+		}
+	}
+
+	fn check_value(&self, value: &str) {
+		let next = self.next.lock().unwrap();
+		let entry = &self.entries[*next];
+		if entry.value != value {
+			panic!("replay diverged at seq {}: received {:?}, the log says {:?}", entry.seq, value, entry.value);
+		}
+	}
+
+	fn advance(&self) {
+		*self.next.lock().unwrap() += 1;
+		self.advanced.notify_all();
+	}
+}
+
+/// Sending half of a replayed channel.
+pub struct ReplayProducer<T: Send + Debug> {
+	inner: Producer<T>,
+	script: Arc<Script>,
+}
+
+/// Receiving half of a replayed channel.
+pub struct ReplayConsumer<T: Send + Debug> {
+	inner: Consumer<T>,
+	script: Arc<Script>,
+}
+
+/// Creates a channel that forces its users into the order of `entries`. A
+/// thread that waits for its turn longer than `patience` panics, since the
+/// program then took a path the log does not know about.
+pub fn replay_channel<T>(entries: Vec<Entry>, patience: Duration) -> (ReplayProducer<T>, ReplayConsumer<T>)
+	where T: Send + Debug
+{
+	let script = Arc::new(Script { entries, next: Mutex::new(0), advanced: Condvar::new(), patience });
+	// unbounded is fine, the log only contains sends that fit when recorded
+	let (px, cx) = super::channel(0);
+	(
+		ReplayProducer { inner: px, script: script.clone() },
+		ReplayConsumer { inner: cx, script },
+	)
+}
+
+impl<T: Send + Debug> ReplayProducer<T> {
+	pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+		if !self.script.wait_turn(Op::Send, Some(&format!("{:?}", value))) {
+			return self.inner.send(value);
+		}
+		let result = self.inner.send(value);
+		self.script.advance();
+		result
+	}
+}
+
+impl<T: Send + Debug> Clone for ReplayProducer<T> {
+	fn clone(&self) -> Self {
+		ReplayProducer { inner: self.inner.clone(), script: self.script.clone() }
+	}
+}
+
+impl<T: Send + Debug> ReplayConsumer<T> {
+	pub fn recv(&self) -> Result<T, RecvError> {
+		if !self.script.wait_turn(Op::Recv, None) {
+			return self.inner.recv();
+		}
+		let result = self.inner.recv();
+		if let Ok(ref value) = result {
+			self.script.check_value(&format!("{:?}", value));
+		}
+		self.script.advance();
+		result
+	}
+}
+
+impl<T: Send + Debug> Clone for ReplayConsumer<T> {
+	fn clone(&self) -> Self {
+		ReplayConsumer { inner: self.inner.clone(), script: self.script.clone() }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::io::Cursor;
+
+	#[derive(Clone)]
+	struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+	impl Write for SharedBuf {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.0.lock().unwrap().extend_from_slice(buf);
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	fn named<F, R>(name: &str, f: F) -> thread::JoinHandle<R>
+		where F: FnOnce() -> R + Send + 'static, R: Send + 'static
+	{
+		thread::Builder::new().name(name.to_string()).spawn(f).unwrap()
+	}
+
+	#[test]
+	fn replay_reproduces_the_recorded_interleaving() {
+		let buf = SharedBuf(Arc::new(Mutex::new(Vec::new())));
+		let (px1, cx) = channel::<u32, _>(16, buf.clone());
+		let px2 = px1.clone();
+
+		let p1 = named("p1", move || for i in 0..50 { px1.send(i).unwrap(); });
+		let p2 = named("p2", move || for i in 100..150 { px2.send(i).unwrap(); });
+		let c = named("c", move || (0..100).map(|_| cx.recv().unwrap()).collect::<Vec<_>>());
+		p1.join().unwrap();
+		p2.join().unwrap();
+		let recorded = c.join().unwrap();
+
+		let log = buf.0.lock().unwrap().clone();
+		let entries = load(Cursor::new(log)).unwrap();
+		assert_eq!(entries.len(), 200);
+
+		for _ in 0..3 {
+			let (px1, cx) = replay_channel::<u32>(entries.clone(), Duration::from_secs(5));
+			let px2 = px1.clone();
+
+			let p1 = named("p1", move || for i in 0..50 { px1.send(i).unwrap(); });
+			let p2 = named("p2", move || for i in 100..150 { px2.send(i).unwrap(); });
+			let c = named("c", move || (0..100).map(|_| cx.recv().unwrap()).collect::<Vec<_>>());
+			p1.join().unwrap();
+			p2.join().unwrap();
+			assert_eq!(c.join().unwrap(), recorded);
+		}
+	}
+
+	#[test]
+	fn replay_reports_divergence() {
+		let entries = load(Cursor::new("0\tp\tsend\t1\n1\tc\trecv\t1\n")).unwrap();
+		let (px, _cx) = replay_channel::<u32>(entries, Duration::from_secs(1));
+
+		let p = named("p", move || px.send(2));
+		assert!(p.join().is_err());
+	}
+
+	#[derive(Clone, Copy)]
+	struct Touchy(u32);
+
+	impl Debug for Touchy {
+		fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+			if self.0 == 13 {
+				panic!("unlucky number");
+			}
+			write!(f, "Touchy({})", self.0)
+		}
+	}
+
+	#[test]
+	fn panicking_debug_impl_stops_recording_but_not_the_channel() {
+		let buf = SharedBuf(Arc::new(Mutex::new(Vec::new())));
+		let (px, cx) = channel(4, buf.clone());
+
+		for i in 12..15 {
+			px.send(Touchy(i)).unwrap();
+		}
+		assert_eq!(failure(&px), Some("unlucky number".to_string()));
+
+		// not poisoned
+		assert_eq!(cx.recv().unwrap().0, 12);
+		assert_eq!(cx.recv().unwrap().0, 13);
+		assert_eq!(cx.recv().unwrap().0, 14);
+
+		let log = buf.0.lock().unwrap().clone();
+		assert_eq!(load(Cursor::new(log)).unwrap().len(), 1);
+	}
+
+	#[test]
+	fn load_rejects_garbage() {
+		assert!(load(Cursor::new("0\tp\tpeek\t1\n")).is_err());
+		assert!(load(Cursor::new("zero\tp\tsend\t1\n")).is_err());
+	}
+}
@@ -0,0 +1,152 @@
+//! Request/response on top of the work queue. `call()` sends the request
+//! together with a `Responder`, and the server answers through it. Every
+//! call gets a channel of its own for the response, with room for exactly
+//! one value, so responses cannot end up with the wrong caller:
+//!
+//! ```
+//! let (client, requests) = spsc::reqresp::req_resp::<u32, u32>(8);
+//! let server = std::thread::spawn(move || {
+//!     while let Ok((n, responder)) = requests.recv() {
+//!         let _ = responder.respond(n * n);
+//!     }
+//! });
+//! assert_eq!(client.call(7).unwrap().wait(), Ok(49));
+//! drop(client);
+//! server.join().unwrap();
+//! ```
+
+use std::time::Duration;
+
+use super::{channel, sync_channel, Consumer, Producer, RecvError, RecvTimeoutError, SendError, TryRecvError};
+
+/// The calling side, can be cloned to call from several threads.
+pub struct ReqRespChannel<Req: Send, Resp: Send> {
+	inner: Producer<(Req, Responder<Resp>)>,
+}
+
+/// The serving side, shared by the servers.
+pub type Requests<Req, Resp> = Consumer<(Req, Responder<Resp>)>;
+
+/// Answers one call.
+pub struct Responder<Resp: Send> {
+	px: Producer<Resp>,
+}
+
+/// A call waiting for its response.
+pub struct Pending<Resp: Send> {
+	cx: Consumer<Resp>,
+}
+
+/// Creates a request/response channel where `call()` blocks while `bound`
+/// requests wait for a server, 0 means no limit.
+pub fn req_resp<Req: Send, Resp: Send>(bound: usize) -> (ReqRespChannel<Req, Resp>, Requests<Req, Resp>) {
+	let (px, cx) = if bound == 0 { channel(0) } else { sync_channel(bound) };
+	(ReqRespChannel { inner: px }, cx)
+}
+
+impl<Req: Send, Resp: Send> ReqRespChannel<Req, Resp> {
+
+	/// Sends `request` to a server. Fails with the request if no server is
+	/// left.
+	pub fn call(&self, request: Req) -> Result<Pending<Resp>, SendError<Req>> {
+		let (px, cx) = sync_channel(1);
+		match self.inner.send((request, Responder { px })) {
+			Ok(()) => Ok(Pending { cx }),
+			Err(SendError((request, _))) => Err(SendError(request)),
+		}
+	}
+}
+
+impl<Req: Send, Resp: Send> Clone for ReqRespChannel<Req, Resp> {
+	fn clone(&self) -> Self {
+		ReqRespChannel { inner: self.inner.clone() }
+	}
+}
+
+impl<Resp: Send> Responder<Resp> {
+	/// Hands `response` to the caller. Fails with it if the caller dropped
+	/// the `Pending` call in the meantime.
+	pub fn respond(self, response: Resp) -> Result<(), SendError<Resp>> {
+		self.px.send(response)
+	}
+}
+
+impl<Resp: Send> Pending<Resp> {
+
+	/// Blocks until the response is there. Fails with `Disconnected` if the
+	/// server dropped the responder without answering, or
+	/// `ProducerPanicked` if it panicked with it.
+	pub fn wait(self) -> Result<Resp, RecvError> {
+		self.cx.recv()
+	}
+
+	/// The response if it is there, the call back otherwise.
+	pub fn try_wait(self) -> Result<Result<Resp, RecvError>, Self> {
+		match self.cx.try_recv() {
+			Ok(response) => Ok(Ok(response)),
+			Err(TryRecvError::Empty) => Err(self),
+			Err(TryRecvError::Poisoned) => Ok(Err(RecvError::Poisoned)),
+			Err(TryRecvError::Disconnected) => Ok(Err(RecvError::Disconnected)),
+		}
+	}
+
+	pub fn wait_timeout(&self, timeout: Duration) -> Result<Resp, RecvTimeoutError> {
+		self.cx.recv_timeout(timeout)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::thread;
+
+	#[test]
+	fn responses_reach_their_callers() {
+		let (client, requests) = req_resp::<u64, u64>(4);
+		let servers: Vec<_> = (0..3).map(|_| {
+			let requests = requests.clone();
+			thread::spawn(move || {
+				while let Ok((n, responder)) = requests.recv() {
+					responder.respond(n * 2).unwrap();
+				}
+			})
+		}).collect();
+		drop(requests);
+
+		let callers: Vec<_> = (0..4u64).map(|t| {
+			let client = client.clone();
+			thread::spawn(move || {
+				for i in 0..50 {
+					let n = t * 1000 + i;
+					assert_eq!(client.call(n).unwrap().wait(), Ok(n * 2));
+				}
+			})
+		}).collect();
+		for caller in callers {
+			caller.join().unwrap();
+		}
+
+		drop(client);
+		for server in servers {
+			server.join().unwrap();
+		}
+	}
+
+	#[test]
+	fn a_dropped_responder_fails_the_call() {
+		let (client, requests) = req_resp::<u32, u32>(0);
+		let pending = client.call(1).unwrap();
+		let pending = pending.try_wait().err().unwrap();
+		let (_, responder) = requests.recv().unwrap();
+		drop(responder);
+		assert_eq!(pending.wait(), Err(RecvError::Disconnected));
+	}
+
+	#[test]
+	fn call_without_server_hands_the_request_back() {
+		let (client, requests) = req_resp::<u32, u32>(0);
+		drop(requests);
+		assert_eq!(client.call(5).err().unwrap().0, 5);
+	}
+}
@@ -0,0 +1,210 @@
+//! A readers-writer lock with a selectable policy, built from a Mutex and a
+//! Condvar like the Semaphore. Unlike `std::sync::RwLock`, which leaves the
+//! policy to the operating system, the policy here is explicit so the classic
+//! trade-offs can be observed:
+//!
+//! - `ReaderPreference`: readers get in whenever no writer is inside. A
+//!   steady stream of readers starves the writers.
+//! - `WriterPreference`: as soon as a writer waits, no new reader gets in. A
+//!   steady stream of writers starves the readers.
+//! - `Fair`: everybody draws a ticket and is served in arrival order, with
+//!   consecutive readers sharing the lock. Nobody starves.
+
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+	ReaderPreference,
+	WriterPreference,
+	Fair,
+}
+
+struct State {
+	readers: usize,
+	writer: bool,
+	waiting_writers: usize,
+	// ticket dispenser for the fair policy
+	next_ticket: u64,
+	serving: u64,
+}
+
+pub struct RwLock<T> {
+	policy: Policy,
+	state: Mutex<State>,
+	changed: Condvar,
+	data: UnsafeCell<T>,
+}
+
+// The state machine above hands out either one &mut T or any number of &T.
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+pub struct ReadGuard<'a, T: 'a> {
+	lock: &'a RwLock<T>,
+}
+
+pub struct WriteGuard<'a, T: 'a> {
+	lock: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+
+	pub fn new(value: T, policy: Policy) -> Self {
+		RwLock {
+			policy,
+			state: Mutex::new(State { readers: 0, writer: false, waiting_writers: 0, next_ticket: 0, serving: 0 }),
+			changed: Condvar::new(),
+			data: UnsafeCell::new(value),
+		}
+	}
+
+	pub fn policy(&self) -> Policy {
+		self.policy
+	}
+
+	fn lock_state(&self) -> MutexGuard<'_, State> {
+		self.state.lock().unwrap()
+	}
+
+	pub fn read(&self) -> ReadGuard<'_, T> {
+		let mut state = self.lock_state();
+		let ticket = state.next_ticket;
+		state.next_ticket += 1;
+
+		loop {
+			let may_enter = match self.policy {
+				Policy::ReaderPreference => !state.writer,
+				Policy::WriterPreference => !state.writer && state.waiting_writers == 0,
+				Policy::Fair => !state.writer && state.serving == ticket,
+			};
+			if may_enter {
+				break;
+			}
+			state = self.changed.wait(state).unwrap();
+		}
+
+		state.readers += 1;
+		if self.policy == Policy::Fair {
+			// let the next in line in, it may be another reader
+			state.serving += 1;
+			self.changed.notify_all();
+		}
+		ReadGuard { lock: self }
+	}
+
+	pub fn write(&self) -> WriteGuard<'_, T> {
+		let mut state = self.lock_state();
+		let ticket = state.next_ticket;
+		state.next_ticket += 1;
+		state.waiting_writers += 1;
+
+		loop {
+			let free = !state.writer && state.readers == 0;
+			let may_enter = match self.policy {
+				Policy::ReaderPreference | Policy::WriterPreference => free,
+				Policy::Fair => free && state.serving == ticket,
+			};
+			if may_enter {
+				break;
+			}
+			state = self.changed.wait(state).unwrap();
+		}
+
+		state.waiting_writers -= 1;
+		state.writer = true;
+		if self.policy == Policy::Fair {
+			state.serving += 1;
+		}
+		WriteGuard { lock: self }
+	}
+
+	pub fn into_inner(self) -> T {
+		self.data.into_inner()
+	}
+}
+
+impl<'a, T> Deref for ReadGuard<'a, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		unsafe { &*self.lock.data.get() }
+	}
+}
+
+impl<'a, T> Drop for ReadGuard<'a, T> {
+	fn drop(&mut self) {
+		let mut state = self.lock.lock_state();
+		state.readers -= 1;
+		if state.readers == 0 {
+			self.lock.changed.notify_all();
+		}
+	}
+}
+
+impl<'a, T> Deref for WriteGuard<'a, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		unsafe { &*self.lock.data.get() }
+	}
+}
+
+impl<'a, T> DerefMut for WriteGuard<'a, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		unsafe { &mut *self.lock.data.get() }
+	}
+}
+
+impl<'a, T> Drop for WriteGuard<'a, T> {
+	fn drop(&mut self) {
+		let mut state = self.lock.lock_state();
+		state.writer = false;
+		self.lock.changed.notify_all();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::sync::Arc;
+	use std::thread;
+
+	#[test]
+	fn readers_share_writers_exclude() {
+		for &policy in &[Policy::ReaderPreference, Policy::WriterPreference, Policy::Fair] {
+			let lock = Arc::new(RwLock::new((0u64, 0u64), policy));
+
+			let threads: Vec<_> = (0..6).map(|i| {
+				let lock = lock.clone();
+				thread::spawn(move || {
+					for _ in 0..500 {
+						if i % 2 == 0 {
+							let mut pair = lock.write();
+							pair.0 += 1;
+							pair.1 += 1;
+						} else {
+							let pair = lock.read();
+							assert_eq!(pair.0, pair.1);
+						}
+					}
+				})
+			}).collect();
+
+			for t in threads {
+				t.join().unwrap();
+			}
+			assert_eq!(Arc::try_unwrap(lock).ok().unwrap().into_inner(), (1500, 1500));
+		}
+	}
+
+	#[test]
+	fn two_readers_at_once() {
+		let lock = RwLock::new(1, Policy::Fair);
+		let a = lock.read();
+		let b = lock.read();
+		assert_eq!(*a + *b, 2);
+	}
+}
@@ -0,0 +1,222 @@
+//! Producer and consumer threads that cannot outlive the code that started
+//! them, on top of `std::thread::scope()`:
+//!
+//! ```
+//! let sum = spsc::scope(|s| {
+//!     s.producer(|px| for i in 1..30 { px.send(i).unwrap(); });
+//!     let consumer = s.consumer(|cx| {
+//!         let mut sum = 0;
+//!         while let Ok(v) = cx.recv() { sum += v; }
+//!         sum
+//!     });
+//!     consumer.join()
+//! });
+//! assert_eq!(sum, 435);
+//! ```
+//!
+//! `scope()` creates the channel, every `producer()` and `consumer()` call
+//! starts a thread with a handle to it, and `scope()` returns only after all
+//! of them are done. Since the threads are scoped they may borrow from the
+//! surrounding function.
+//!
+//! Panics are passed on instead of surfacing as a failed `join()` that has to
+//! be unwrapped: `Handle::join()` panics with the thread's own panic, and a
+//! thread that panicked without being joined makes `scope()` panic with its
+//! message. The channel has panic propagation turned on, so consumers get
+//! `RecvError::ProducerPanicked` rather than waiting for a producer that is
+//! gone.
+//!
+//! The scope holds a producer of its own until `f` returns, so consumers do
+//! not see a disconnect between two `producer()` calls. Joining a consumer
+//! inside `f` means that no more producers are coming: the scope lets go of
+//! its producer then, and the consumer finishes once the producer threads
+//! are done.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::{channel, panic_message, Consumer, Producer};
+
+pub struct Scope<'scope, 'env: 'scope, T: Send + 'scope> {
+	threads: &'scope thread::Scope<'scope, 'env>,
+	// None once a consumer was joined
+	px: Arc<Mutex<Option<Producer<T>>>>,
+	cx: Consumer<T>,
+	// message of the first thread that panicked
+	first_panic: Arc<Mutex<Option<String>>>,
+}
+
+/// A thread started by `Scope::producer()` or `Scope::consumer()`.
+pub struct Handle<'scope, R> {
+	inner: thread::ScopedJoinHandle<'scope, R>,
+	// drops the scope's producer, for consumers
+	release: Option<Box<dyn Fn() + Send + 'scope>>,
+}
+
+impl<'scope, R> Handle<'scope, R> {
+	/// Waits for the thread and returns what it returned. If it panicked,
+	/// the panic continues in the calling thread.
+	pub fn join(self) -> R {
+		if let Some(release) = self.release {
+			release();
+		}
+		match self.inner.join() {
+			Ok(result) => result,
+			Err(payload) => panic::resume_unwind(payload),
+		}
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.inner.is_finished()
+	}
+}
+
+/// Runs `f` with a fresh unbounded channel and waits for every thread it
+/// started on it, see the module documentation.
+pub fn scope<'env, T, F, R>(f: F) -> R
+	where T: Send + 'env, F: for<'scope> FnOnce(&Scope<'scope, 'env, T>) -> R
+{
+	let first_panic = Arc::new(Mutex::new(None));
+
+	let outcome = panic::catch_unwind(AssertUnwindSafe(|| thread::scope(|threads| {
+		let (px, cx) = channel(0);
+		px.propagate_panics();
+		let scope = Scope { threads, px: Arc::new(Mutex::new(Some(px))), cx, first_panic: first_panic.clone() };
+		// the scope's own handles go away at the end of this closure, before
+		// thread::scope() waits for the threads
+		panic::catch_unwind(AssertUnwindSafe(|| f(&scope)))
+	})));
+
+	match outcome {
+		Ok(Ok(result)) => result,
+		// f panicked, maybe in Handle::join()
+		Ok(Err(payload)) => panic::resume_unwind(payload),
+		// a thread panicked and nobody joined it
+		Err(payload) => match first_panic.lock().unwrap_or_else(|e| e.into_inner()).take() {
+			Some(message) => panic!("{}", message),
+			None => panic::resume_unwind(payload),
+		},
+	}
+}
+
+impl<'scope, 'env, T: Send + 'scope> Scope<'scope, 'env, T> {
+	/// Starts a thread running `f` with a producer for the channel.
+	pub fn producer<F, R>(&self, f: F) -> Handle<'scope, R>
+		where F: FnOnce(Producer<T>) -> R + Send + 'scope, R: Send + 'scope
+	{
+		let px = match *self.px.lock().unwrap() {
+			Some(ref px) => px.clone(),
+			None => panic!("Scope::producer() called after a consumer was joined."),
+		};
+		let first_panic = self.first_panic.clone();
+		let inner = self.threads.spawn(move || {
+			match px.run_catching(f) {
+				Ok(result) => result,
+				Err(payload) => {
+					remember(&first_panic, &*payload);
+					panic::resume_unwind(payload)
+				}
+			}
+		});
+		Handle { inner, release: None }
+	}
+
+	/// Starts a thread running `f` with a consumer for the channel.
+	pub fn consumer<F, R>(&self, f: F) -> Handle<'scope, R>
+		where F: FnOnce(Consumer<T>) -> R + Send + 'scope, R: Send + 'scope
+	{
+		let cx = self.cx.clone();
+		let first_panic = self.first_panic.clone();
+		let inner = self.threads.spawn(move || {
+			match panic::catch_unwind(AssertUnwindSafe(move || f(cx))) {
+				Ok(result) => result,
+				Err(payload) => {
+					remember(&first_panic, &*payload);
+					panic::resume_unwind(payload)
+				}
+			}
+		});
+		let px = self.px.clone();
+		let release = move || {
+			px.lock().unwrap().take();
+		};
+		Handle { inner, release: Some(Box::new(release)) }
+	}
+}
+
+fn remember(first_panic: &Mutex<Option<String>>, payload: &(dyn Any + Send)) {
+	let mut first = first_panic.lock().unwrap_or_else(|e| e.into_inner());
+	if first.is_none() {
+		*first = Some(panic_message(payload));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::RecvError;
+
+	#[test]
+	fn threads_may_borrow_from_the_caller() {
+		let input: Vec<u64> = (1..=100).collect();
+		let sum = scope(|s| {
+			s.producer(|px| for &v in &input { px.send(v).unwrap(); });
+			s.consumer(|cx| {
+				let mut sum = 0;
+				while let Ok(v) = cx.recv() {
+					sum += v;
+				}
+				sum
+			}).join()
+		});
+		assert_eq!(sum, 5050);
+	}
+
+	#[test]
+	fn consumers_wait_for_every_producer() {
+		let count = scope(|s| {
+			let consumer = s.consumer(|cx| {
+				let mut count = 0;
+				while cx.recv().is_ok() {
+					count += 1;
+				}
+				count
+			});
+			for _ in 0..3 {
+				s.producer(|px| for i in 0..100 { px.send(i).unwrap(); });
+			}
+			consumer.join()
+		});
+		assert_eq!(count, 300);
+	}
+
+	#[test]
+	fn a_producer_panic_reaches_the_consumer_and_the_caller() {
+		let seen = Mutex::new(None);
+		let result = panic::catch_unwind(AssertUnwindSafe(|| scope(|s| {
+			s.producer(|px| {
+				px.send(1u32).unwrap();
+				panic!("producer gave up");
+			});
+			s.consumer(|cx| {
+				assert_eq!(cx.recv(), Ok(1));
+				*seen.lock().unwrap() = Some(cx.recv());
+			});
+		})));
+
+		let payload = result.unwrap_err();
+		assert_eq!(panic_message(&*payload), "producer gave up");
+		assert_eq!(seen.into_inner().unwrap(), Some(Err(RecvError::ProducerPanicked("producer gave up".to_string()))));
+	}
+
+	#[test]
+	fn join_passes_the_panic_on() {
+		let result = panic::catch_unwind(|| scope(|s: &Scope<u32>| {
+			s.consumer(|_cx| -> u32 { panic!("consumer down") }).join()
+		}));
+		assert_eq!(panic_message(&*result.unwrap_err()), "consumer down");
+	}
+}
@@ -0,0 +1,364 @@
+//! Waiting on several channels at once, and `merge()` on top of it.
+//!
+//! A `Select` owns a list of consumers and registers a `Signal` with each of
+//! their channels. Every value that becomes visible in one of them, and every
+//! disconnect, raises the signal. `recv()` polls the sources with
+//! `try_recv()` and sleeps on the signal while all of them are empty. The
+//! signal stays raised until the next round of polling starts, so a value
+//! that arrives between polling and going to sleep is not missed.
+//!
+//! Which source is polled first is up to the `Fairness` mode. Always starting
+//! with the first one is simple and gives the sources a priority order, but
+//! under sustained load the later sources starve. Round robin moves the
+//! starting point along by one on every call instead.
+
+use std::cell::Cell;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use super::{sync_channel, Consumer, RecvError, TryRecvError};
+
+pub(crate) struct Signal {
+	raised: Mutex<bool>,
+	changed: Condvar,
+}
+
+impl Signal {
+	pub(crate) fn new() -> Arc<Self> {
+		Arc::new(Signal { raised: Mutex::new(false), changed: Condvar::new() })
+	}
+
+	pub(crate) fn raise(&self) {
+		*self.raised.lock().unwrap() = true;
+		self.changed.notify_all();
+	}
+
+	pub(crate) fn lower(&self) {
+		*self.raised.lock().unwrap() = false;
+	}
+
+	pub(crate) fn wait(&self) {
+		let mut raised = self.raised.lock().unwrap();
+		while !*raised {
+			raised = self.changed.wait(raised).unwrap();
+		}
+	}
+}
+
+/// Which source a `Select` polls first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fairness {
+	/// Always the first one, so earlier sources take priority.
+	FirstReady,
+	/// The next one after the source polled first last time, so each
+	/// source comes first equally often.
+	RoundRobin,
+}
+
+/// Receives from whichever of several consumers has a value.
+pub struct Select<T: Send> {
+	sources: Vec<Consumer<T>>,
+	signal: Arc<Signal>,
+	fairness: Fairness,
+	// where the next round robin poll starts
+	next: Cell<usize>,
+}
+
+impl<T: Send> Select<T> {
+	pub fn new(sources: Vec<Consumer<T>>) -> Self {
+		Self::with_fairness(sources, Fairness::FirstReady)
+	}
+
+	pub fn with_fairness(sources: Vec<Consumer<T>>, fairness: Fairness) -> Self {
+		let signal = Signal::new();
+		for source in &sources {
+			if let Ok(mut state) = source.shared.lock() {
+				state.watchers.push(signal.clone());
+			}
+		}
+		Select { sources, signal, fairness, next: Cell::new(0) }
+	}
+
+	pub fn len(&self) -> usize {
+		self.sources.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.sources.is_empty()
+	}
+
+	/// Blocks until one of the sources has a value and returns it together
+	/// with the index of its source. If several have a value the fairness
+	/// mode decides. Fails with `Disconnected` once every source is
+	/// disconnected and drained.
+	pub fn recv(&self) -> Result<(usize, T), RecvError> {
+		let start = self.start();
+		loop {
+			self.signal.lower();
+			match self.poll(start) {
+				Ok(received) => return Ok(received),
+				Err(TryRecvError::Disconnected) => return Err(RecvError::Disconnected),
+				Err(TryRecvError::Poisoned) => return Err(RecvError::Poisoned),
+				Err(TryRecvError::Empty) => self.signal.wait(),
+			}
+		}
+	}
+
+	/// Like `recv()`, but returns `Empty` instead of blocking.
+	pub fn try_recv(&self) -> Result<(usize, T), TryRecvError> {
+		self.poll(self.start())
+	}
+
+	fn start(&self) -> usize {
+		match self.fairness {
+			Fairness::FirstReady => 0,
+			Fairness::RoundRobin => {
+				let start = self.next.get();
+				self.next.set((start + 1) % self.sources.len().max(1));
+				start
+			}
+		}
+	}
+
+	fn poll(&self, start: usize) -> Result<(usize, T), TryRecvError> {
+		let n = self.sources.len();
+		let mut disconnected = 0;
+		for i in (0..n).map(|k| (start + k) % n) {
+			match self.sources[i].try_recv() {
+				Ok(value) => return Ok((i, value)),
+				Err(TryRecvError::Empty) => {}
+				Err(TryRecvError::Disconnected) => disconnected += 1,
+				Err(TryRecvError::Poisoned) => return Err(TryRecvError::Poisoned),
+			}
+		}
+		if disconnected == n {
+			Err(TryRecvError::Disconnected)
+		} else {
+			Err(TryRecvError::Empty)
+		}
+	}
+
+	/// Gives back the consumers, in the order they were passed in.
+	pub fn into_inner(mut self) -> Vec<Consumer<T>> {
+		self.unregister();
+		::std::mem::take(&mut self.sources)
+	}
+
+	fn unregister(&self) {
+		for source in &self.sources {
+			if let Ok(mut state) = source.shared.lock() {
+				state.watchers.retain(|w| !Arc::ptr_eq(w, &self.signal));
+			}
+		}
+	}
+}
+
+impl<T: Send> Drop for Select<T> {
+	fn drop(&mut self) {
+		self.unregister();
+	}
+}
+
+/// Joins several consumers into one. A thread forwards the values of all
+/// sources into the returned consumer, through a channel with room for a
+/// single value so it does not read ahead. The sources are polled round
+/// robin, so a source that always has a value cannot keep the others
+/// waiting. The returned consumer ends once every source has, with the first
+/// error other than a plain disconnect that a source ended with, if any.
+/// Dropping it stops the thread right away.
+pub fn merge<T: Send + 'static>(sources: Vec<Consumer<T>>) -> Consumer<T> {
+	let (px, cx) = sync_channel(1);
+	let select = Select::with_fairness(sources, Fairness::RoundRobin);
+	// the signal is raised when the merged consumer is dropped as well
+	px.shared.lock().unwrap().watchers.push(select.signal.clone());
+
+	thread::spawn(move || {
+		loop {
+			let start = select.start();
+			let received = loop {
+				select.signal.lower();
+				if px.shared.lock().unwrap().consumers == 0 {
+					return;
+				}
+				match select.poll(start) {
+					Err(TryRecvError::Empty) => select.signal.wait(),
+					received => break received,
+				}
+			};
+			match received {
+				Ok((_, value)) => {
+					if px.send(value).is_err() {
+						// nobody is listening anymore
+						return;
+					}
+				}
+				Err(TryRecvError::Poisoned) => return px.pass_on(&RecvError::Poisoned),
+				Err(_) => {
+					// every source is drained, so recv() just reports how it ended
+					let ended = select.sources.iter()
+						.filter_map(|source| source.recv().err())
+						.find(|e| *e != RecvError::Disconnected);
+					if let Some(e) = ended {
+						px.pass_on(&e);
+					}
+					return;
+				}
+			}
+		}
+	});
+	cx
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::super::channel;
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn select_wakes_up_for_any_source() {
+		let (px1, cx1) = channel::<u32>(4);
+		let (px2, cx2) = channel::<u32>(4);
+		let select = Select::new(vec![cx1, cx2]);
+		assert_eq!(select.try_recv(), Err(TryRecvError::Empty));
+
+		let producer_thread = thread::spawn(move || {
+			thread::sleep(Duration::from_millis(10));
+			px2.send(7).unwrap();
+			thread::sleep(Duration::from_millis(10));
+			drop(px1);
+		});
+
+		assert_eq!(select.recv(), Ok((1, 7)));
+		assert_eq!(select.recv(), Err(RecvError::Disconnected));
+		producer_thread.join().unwrap();
+
+		let sources = select.into_inner();
+		assert!(sources.iter().all(|cx| cx.shared.lock().unwrap().watchers.is_empty()));
+	}
+
+	#[test]
+	fn merge_forwards_everything_from_every_source() {
+		let mut sources = Vec::new();
+		let mut producers = Vec::new();
+		for k in 0..4u32 {
+			let (px, cx) = channel::<u32>(16);
+			sources.push(cx);
+			producers.push(thread::spawn(move || {
+				for i in 0..250 {
+					px.send(k * 1000 + i).unwrap();
+				}
+			}));
+		}
+
+		let merged = merge(sources);
+		let mut received = Vec::new();
+		while let Ok(value) = merged.recv() {
+			received.push(value);
+		}
+		for producer in producers {
+			producer.join().unwrap();
+		}
+
+		// every source in its own order, all of them complete
+		for k in 0..4u32 {
+			let from_k: Vec<_> = received.iter().filter(|&&v| v / 1000 == k).map(|&v| v % 1000).collect();
+			assert_eq!(from_k, (0..250).collect::<Vec<_>>());
+		}
+	}
+
+	#[test]
+	fn merge_takes_turns_between_busy_sources() {
+		let (px1, cx1) = channel::<u32>(0);
+		let (px2, cx2) = channel::<u32>(0);
+		for _ in 0..10 {
+			px1.send(1).unwrap();
+			px2.send(2).unwrap();
+		}
+		drop(px1);
+		drop(px2);
+
+		let merged = merge(vec![cx1, cx2]);
+		let received: Vec<_> = (0..20).map(|_| merged.recv().unwrap()).collect();
+		assert_eq!(received, [1, 2].iter().cycle().cloned().take(20).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn dropping_the_merged_consumer_stops_the_thread() {
+		let (px, cx) = channel::<u32>(4);
+		let merged = merge(vec![cx]);
+		let sources_left = || px.shared.lock().unwrap().consumers;
+		assert_eq!(sources_left(), 1);
+
+		drop(merged);
+		// the thread drops the source when it ends, without px sending
+		while sources_left() > 0 {
+			thread::sleep(Duration::from_millis(1));
+		}
+		assert!(px.send(1).is_err());
+	}
+
+	#[test]
+	fn merge_passes_on_how_a_source_ended() {
+		let (px1, cx1) = channel::<u32>(4);
+		let (px2, cx2) = channel::<u32>(4);
+		let merged = merge(vec![cx1, cx2]);
+		let producer_thread = px1.spawn(|_| panic!("sensor on fire"));
+		assert!(producer_thread.join().is_err());
+
+		px2.send(5).unwrap();
+		drop(px2);
+		assert_eq!(merged.recv(), Ok(5));
+		assert_eq!(merged.recv(), Err(RecvError::ProducerPanicked("sensor on fire".to_string())));
+	}
+
+	// Three producers that keep their channels full for as long as the test
+	// runs, and how often each source won out of 3000 receives.
+	fn shares_under_load(fairness: Fairness) -> Vec<usize> {
+		let mut sources = Vec::new();
+		let mut producers = Vec::new();
+		for k in 0..3 {
+			let (px, cx) = sync_channel::<usize>(8);
+			sources.push(cx);
+			producers.push(thread::spawn(move || while px.send(k).is_ok() {}));
+		}
+		// let every channel fill up before the first receive
+		thread::sleep(Duration::from_millis(20));
+
+		let select = Select::with_fairness(sources, fairness);
+		let mut shares = vec![0; 3];
+		for _ in 0..3000 {
+			shares[select.recv().unwrap().0] += 1;
+		}
+
+		drop(select);
+		for producer in producers {
+			producer.join().unwrap();
+		}
+		shares
+	}
+
+	#[test]
+	fn round_robin_does_not_starve_anybody() {
+		let shares = shares_under_load(Fairness::RoundRobin);
+		assert!(shares.iter().all(|&n| n >= 500), "{:?}", shares);
+
+		// whereas first ready mostly serves the first source
+		let shares = shares_under_load(Fairness::FirstReady);
+		assert!(shares[0] > shares[2], "{:?}", shares);
+	}
+
+	#[test]
+	fn round_robin_rotates_the_first_poll() {
+		let (px1, cx1) = channel::<u32>(4);
+		let (px2, cx2) = channel::<u32>(4);
+		for _ in 0..2 {
+			px1.send(1).unwrap();
+			px2.send(2).unwrap();
+		}
+		let select = Select::with_fairness(vec![cx1, cx2], Fairness::RoundRobin);
+		let received: Vec<_> = (0..4).map(|_| select.try_recv().unwrap().1).collect();
+		assert_eq!(received, [1, 2, 1, 2]);
+	}
+}
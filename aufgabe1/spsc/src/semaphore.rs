@@ -0,0 +1,130 @@
+//! A counting semaphore built from a Mutex and a Condvar, the way it is
+//! introduced in the lecture. With one permit it doubles as a mutex that is
+//! not tied to the data it protects (e.g. a fork in the dining philosophers).
+
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+pub struct Semaphore {
+	permits: Mutex<usize>,
+	released: Condvar,
+}
+
+/// Releases its permit when dropped, see `Semaphore::access()`.
+pub struct SemaphoreGuard<'a> {
+	semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+
+	pub fn new(permits: usize) -> Self {
+		Semaphore { permits: Mutex::new(permits), released: Condvar::new() }
+	}
+
+	/// P(): blocks until a permit is available and takes it.
+	pub fn acquire(&self) {
+		let mut permits = self.permits.lock().unwrap();
+		while *permits == 0 {
+			permits = self.released.wait(permits).unwrap();
+		}
+		*permits -= 1;
+	}
+
+	/// Takes a permit if one is available right now.
+	pub fn try_acquire(&self) -> bool {
+		let mut permits = self.permits.lock().unwrap();
+		if *permits == 0 {
+			return false;
+		}
+		*permits -= 1;
+		true
+	}
+
+	/// Like `acquire()`, but gives up after `timeout`. Returns whether a
+	/// permit was taken.
+	pub fn acquire_timeout(&self, timeout: Duration) -> bool {
+		let deadline = Instant::now() + timeout;
+		let mut permits = self.permits.lock().unwrap();
+		while *permits == 0 {
+			let now = Instant::now();
+			if now >= deadline {
+				return false;
+			}
+			permits = self.released.wait_timeout(permits, deadline - now).unwrap().0;
+		}
+		*permits -= 1;
+		true
+	}
+
+	/// V(): hands a permit back and wakes up one waiter.
+	pub fn release(&self) {
+		let mut permits = self.permits.lock().unwrap();
+		*permits += 1;
+		self.released.notify_one();
+	}
+
+	/// Acquires a permit that is released again when the guard goes away.
+	pub fn access(&self) -> SemaphoreGuard<'_> {
+		self.acquire();
+		SemaphoreGuard { semaphore: self }
+	}
+
+	pub fn available(&self) -> usize {
+		*self.permits.lock().unwrap()
+	}
+}
+
+impl<'a> Drop for SemaphoreGuard<'a> {
+	fn drop(&mut self) {
+		self.semaphore.release();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Arc;
+	use std::thread;
+
+	#[test]
+	fn permits_are_counted() {
+		let sem = Semaphore::new(2);
+		assert!(sem.try_acquire());
+		{
+			let _guard = sem.access();
+			assert_eq!(sem.available(), 0);
+			assert!(!sem.try_acquire());
+			assert!(!sem.acquire_timeout(Duration::from_millis(5)));
+		}
+		assert_eq!(sem.available(), 1);
+		sem.release();
+		assert_eq!(sem.available(), 2);
+	}
+
+	#[test]
+	fn at_most_n_threads_inside() {
+		let sem = Arc::new(Semaphore::new(3));
+		let inside = Arc::new(AtomicUsize::new(0));
+		let max = Arc::new(AtomicUsize::new(0));
+
+		let threads: Vec<_> = (0..8).map(|_| {
+			let (sem, inside, max) = (sem.clone(), inside.clone(), max.clone());
+			thread::spawn(move || {
+				for _ in 0..100 {
+					let _guard = sem.access();
+					let now = inside.fetch_add(1, Ordering::SeqCst) + 1;
+					max.fetch_max(now, Ordering::SeqCst);
+					thread::yield_now();
+					inside.fetch_sub(1, Ordering::SeqCst);
+				}
+			})
+		}).collect();
+
+		for t in threads {
+			t.join().unwrap();
+		}
+		assert!(max.load(Ordering::SeqCst) <= 3);
+	}
+}
@@ -0,0 +1,219 @@
+//! A single value published through a sequence lock. The writer bumps a
+//! sequence counter to an odd number, writes, and bumps it to the next even
+//! number. Readers copy the value optimistically and retry if the counter was
+//! odd or changed in between, so they never block the writer. Meant for
+//! high-rate values like telemetry, where only the latest snapshot matters and
+//! queueing every update would be wasted work.
+//!
+//! See https://en.wikipedia.org/wiki/Seqlock
+//!
+//! A reader may copy while the writer writes, so the value is kept as a row
+//! of `AtomicUsize` words and copied word by word: a plain copy racing with a
+//! write would be undefined behaviour even if the torn result is thrown away.
+//! That limits the cell to `Words` types, plain data that is a whole number
+//! of words. The tests run clean under Miri (`cargo +nightly miri test`), as
+//! do those of triple.rs and sigsafe.rs.
+
+use std::hint;
+use std::marker::PhantomData;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+
+/// Types a `SeqLockCell` can hold.
+///
+/// # Safety
+///
+/// The type has to be `Copy`, exactly as large as a whole number of `usize`
+/// words and free of padding bytes, pointers and references, so that copying
+/// it word by word as integers reads only initialized bytes and rebuilds the
+/// same value.
+pub unsafe trait Words: Copy {}
+
+unsafe impl Words for usize {}
+unsafe impl Words for isize {}
+unsafe impl Words for u64 {}
+unsafe impl Words for i64 {}
+unsafe impl Words for f64 {}
+unsafe impl<T: Words, const N: usize> Words for [T; N] {}
+
+pub struct SeqLockCell<T: Words> {
+	seq: AtomicUsize,
+	words: Box<[AtomicUsize]>,
+	value: PhantomData<T>,
+}
+
+const WORD: usize = mem::size_of::<usize>();
+
+impl<T: Words> SeqLockCell<T> {
+
+	pub fn new(value: T) -> Self {
+		assert!(mem::size_of::<T>().is_multiple_of(WORD), "SeqLockCell::new() value is not a whole number of words.");
+		let words = (0..mem::size_of::<T>() / WORD).map(|_| AtomicUsize::new(0)).collect();
+		let cell = SeqLockCell { seq: AtomicUsize::new(0), words, value: PhantomData };
+		cell.write_words(&value);
+		cell
+	}
+
+	/// Returns the most recently stored value. Spins while a store is in
+	/// progress.
+	pub fn load(&self) -> T {
+		loop {
+			let before = self.seq.load(Ordering::Acquire);
+			if before & 1 == 1 {
+				hint::spin_loop();
+				continue;
+			}
+
+			// The copy may be torn if a writer got in. Keep it uninitialized
+			// until the sequence number says it is not.
+			let mut value = MaybeUninit::<T>::uninit();
+			let target = value.as_mut_ptr() as *mut usize;
+			for (i, word) in self.words.iter().enumerate() {
+				unsafe { ptr::write_unaligned(target.add(i), word.load(Ordering::Relaxed)) };
+			}
+			fence(Ordering::Acquire);
+
+			if self.seq.load(Ordering::Relaxed) == before {
+				return unsafe { value.assume_init() };
+			}
+		}
+	}
+
+	/// Publishes a new value. Writers take turns, so several threads may
+	/// store, but the cell is designed for a single one.
+	pub fn store(&self, value: T) {
+		let mut seq = self.seq.load(Ordering::Relaxed);
+		loop {
+			if seq & 1 == 0 {
+				match self.seq.compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed) {
+					Ok(_) => break,
+					Err(current) => seq = current,
+				}
+			} else {
+				hint::spin_loop();
+				seq = self.seq.load(Ordering::Relaxed);
+			}
+		}
+		fence(Ordering::Release);
+
+		self.write_words(&value);
+
+		self.seq.store(seq + 2, Ordering::Release);
+	}
+
+	fn write_words(&self, value: &T) {
+		let source = value as *const T as *const usize;
+		for (i, word) in self.words.iter().enumerate() {
+			word.store(unsafe { ptr::read_unaligned(source.add(i)) }, Ordering::Relaxed);
+		}
+	}
+
+	/// How many stores have completed so far.
+	pub fn version(&self) -> usize {
+		self.seq.load(Ordering::Acquire) / 2
+	}
+
+	pub fn into_inner(self) -> T {
+		self.load()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::sync::Arc;
+	use std::thread;
+	use std::time::Duration;
+
+	// Miri is a few thousand times slower, keep its runs short
+	const ROUNDS: u64 = if cfg!(miri) { 200 } else { 10000 };
+
+	#[test]
+	fn load_sees_the_last_store() {
+		let cell = SeqLockCell::new(1u64);
+		assert_eq!(cell.load(), 1);
+		cell.store(2);
+		cell.store(3);
+		assert_eq!(cell.load(), 3);
+		assert_eq!(cell.version(), 2);
+		assert_eq!(cell.into_inner(), 3);
+	}
+
+	#[test]
+	fn readers_never_see_a_torn_value() {
+		let cell = Arc::new(SeqLockCell::new([0u64; 8]));
+
+		let readers: Vec<_> = (0..3).map(|_| {
+			let cell = cell.clone();
+			thread::spawn(move || {
+				for _ in 0..ROUNDS {
+					let snapshot = cell.load();
+					assert!(snapshot.iter().all(|&x| x == snapshot[0]));
+				}
+			})
+		}).collect();
+
+		for i in 0..ROUNDS {
+			cell.store([i; 8]);
+		}
+
+		for reader in readers {
+			reader.join().unwrap();
+		}
+	}
+
+	#[test]
+	fn load_waits_for_a_store_in_progress() {
+		let cell = Arc::new(SeqLockCell::new(1usize));
+
+		// half way through a store, as far as readers can tell
+		cell.seq.store(1, Ordering::Release);
+
+		let reader = {
+			let cell = cell.clone();
+			thread::spawn(move || cell.load())
+		};
+		thread::sleep(Duration::from_millis(10));
+
+		cell.words[0].store(2, Ordering::Relaxed);
+		cell.seq.store(2, Ordering::Release);
+
+		assert_eq!(reader.join().unwrap(), 2);
+		assert_eq!(cell.version(), 1);
+	}
+
+	// a pair that is only consistent if both halves come from one store
+	#[derive(Clone, Copy, Debug, PartialEq)]
+	#[repr(C)]
+	struct Reading {
+		id: u64,
+		twice: u64,
+	}
+
+	unsafe impl Words for Reading {}
+
+	#[test]
+	fn user_types_made_of_words_are_never_torn() {
+		let cell = Arc::new(SeqLockCell::new(Reading { id: 0, twice: 0 }));
+
+		let readers: Vec<_> = (0..2).map(|_| {
+			let cell = cell.clone();
+			thread::spawn(move || {
+				for _ in 0..ROUNDS {
+					let reading = cell.load();
+					assert_eq!(reading.twice, reading.id * 2);
+				}
+			})
+		}).collect();
+
+		for i in 0..ROUNDS {
+			cell.store(Reading { id: i, twice: i * 2 });
+		}
+
+		for reader in readers {
+			reader.join().unwrap();
+		}
+	}
+}
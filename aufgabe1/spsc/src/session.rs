@@ -0,0 +1,214 @@
+//! Experimental: channels whose protocol is part of their type. A protocol is
+//! written as a chain of `Tx` (send), `Rx` (receive), `Choose`/`Offer` (one
+//! side picks a branch, the other follows) ending in `End`. `session()`
+//! returns one end with the protocol and one with its dual, and every step
+//! consumes the end and returns it with the rest of the protocol, so sending
+//! out of turn or using a step twice does not compile:
+//!
+//! ```
+//! use spsc::session::{session, End, Rx, Tx};
+//!
+//! type Server = Rx<u32, Tx<String, End>>;
+//! let (server, client) = session::<Server>();
+//!
+//! let thread = std::thread::spawn(move || {
+//!     let (n, server) = server.recv().unwrap();
+//!     server.send(format!("#{}", n)).close();
+//! });
+//! let (reply, client) = client.send(7).recv().unwrap();
+//! client.close();
+//! assert_eq!(reply, "#7");
+//! thread.join().unwrap();
+//! ```
+//!
+//! ```compile_fail
+//! use spsc::session::{session, End, Rx, Tx};
+//!
+//! let (server, _client) = session::<Rx<u32, Tx<String, End>>>();
+//! // the server has to receive first
+//! server.send("hello".to_string());
+//! ```
+//!
+//! What the types cannot do is make a step happen: an end can be dropped
+//! half way through the protocol like any other value. `Chan` is
+//! `#[must_use]`, so the compiler warns when a step's result is thrown away,
+//! but an end that is dropped explicitly or goes out of scope is only noticed
+//! by the other side, at runtime.
+//!
+//! There is no recursion, a protocol has a fixed number of steps. The other
+//! end going away is still a runtime error: `recv()` and `offer()` fail with
+//! `RecvError` then, while `send()` ignores it, the error shows up on the
+//! next receive.
+
+use std::any::Any;
+use std::marker::PhantomData;
+
+use super::duplex::{duplex, Duplex};
+use super::RecvError;
+
+/// Send a `T`, then continue with `P`.
+pub struct Tx<T, P>(PhantomData<(T, P)>);
+/// Receive a `T`, then continue with `P`.
+pub struct Rx<T, P>(PhantomData<(T, P)>);
+/// Pick `L` or `R`.
+pub struct Choose<L, R>(PhantomData<(L, R)>);
+/// Follow the other side's choice between `L` and `R`.
+pub struct Offer<L, R>(PhantomData<(L, R)>);
+/// The protocol is over.
+pub struct End;
+
+/// The protocol the other end follows.
+pub trait Protocol {
+	type Dual: Protocol;
+}
+
+impl<T, P: Protocol> Protocol for Tx<T, P> {
+	type Dual = Rx<T, P::Dual>;
+}
+
+impl<T, P: Protocol> Protocol for Rx<T, P> {
+	type Dual = Tx<T, P::Dual>;
+}
+
+impl<L: Protocol, R: Protocol> Protocol for Choose<L, R> {
+	type Dual = Offer<L::Dual, R::Dual>;
+}
+
+impl<L: Protocol, R: Protocol> Protocol for Offer<L, R> {
+	type Dual = Choose<L::Dual, R::Dual>;
+}
+
+impl Protocol for End {
+	type Dual = End;
+}
+
+type Message = Box<dyn Any + Send>;
+
+/// One end of a session, at the step `P` of the protocol.
+#[must_use = "a session end that is dropped before the protocol is over leaves the other side with an error"]
+pub struct Chan<P> {
+	inner: Duplex<Message, Message>,
+	_protocol: PhantomData<P>,
+}
+
+/// The branch the other side chose, see `Chan::offer()`.
+pub enum Branch<L, R> {
+	Left(L),
+	Right(R),
+}
+
+/// Creates both ends of a session following `P`.
+pub fn session<P: Protocol>() -> (Chan<P>, Chan<P::Dual>) {
+	let (a, b) = duplex(1);
+	(Chan::new(a), Chan::new(b))
+}
+
+impl<P> Chan<P> {
+	fn new(inner: Duplex<Message, Message>) -> Self {
+		Chan { inner, _protocol: PhantomData }
+	}
+
+	fn next<Q>(self) -> Chan<Q> {
+		Chan::new(self.inner)
+	}
+
+	fn put<T: Send + 'static>(&self, value: T) {
+		// a vanished other end shows up on the next receive
+		let _ = self.inner.send(Box::new(value));
+	}
+
+	fn take<T: 'static>(&self) -> Result<T, RecvError> {
+		match self.inner.recv()?.downcast() {
+			Ok(value) => Ok(*value),
+			Err(_) => panic!("Chan::recv() other end broke the protocol."),
+		}
+	}
+}
+
+impl<T: Send + 'static, P> Chan<Tx<T, P>> {
+	pub fn send(self, value: T) -> Chan<P> {
+		self.put(value);
+		self.next()
+	}
+}
+
+impl<T: Send + 'static, P> Chan<Rx<T, P>> {
+	pub fn recv(self) -> Result<(T, Chan<P>), RecvError> {
+		let value = self.take()?;
+		Ok((value, self.next()))
+	}
+}
+
+impl<L, R> Chan<Choose<L, R>> {
+	pub fn left(self) -> Chan<L> {
+		self.put(true);
+		self.next()
+	}
+
+	pub fn right(self) -> Chan<R> {
+		self.put(false);
+		self.next()
+	}
+}
+
+impl<L, R> Chan<Offer<L, R>> {
+	/// Waits for the other side to choose.
+	pub fn offer(self) -> Result<Branch<Chan<L>, Chan<R>>, RecvError> {
+		if self.take::<bool>()? {
+			Ok(Branch::Left(self.next()))
+		} else {
+			Ok(Branch::Right(self.next()))
+		}
+	}
+}
+
+impl Chan<End> {
+	pub fn close(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::thread;
+
+	// the client either asks for a sum or quits
+	type Calculator = Offer<Rx<u32, Rx<u32, Tx<u32, End>>>, End>;
+
+	fn serve(server: Chan<Calculator>) -> Option<u32> {
+		match server.offer().unwrap() {
+			Branch::Left(server) => {
+				let (a, server) = server.recv().unwrap();
+				let (b, server) = server.recv().unwrap();
+				server.send(a + b).close();
+				Some(a + b)
+			}
+			Branch::Right(server) => {
+				server.close();
+				None
+			}
+		}
+	}
+
+	#[test]
+	fn both_branches_of_an_offer() {
+		let (server, client) = session::<Calculator>();
+		let server_thread = thread::spawn(move || serve(server));
+		let (sum, client) = client.left().send(2).send(3).recv().unwrap();
+		client.close();
+		assert_eq!(sum, 5);
+		assert_eq!(server_thread.join().unwrap(), Some(5));
+
+		let (server, client) = session::<Calculator>();
+		let server_thread = thread::spawn(move || serve(server));
+		client.right().close();
+		assert_eq!(server_thread.join().unwrap(), None);
+	}
+
+	#[test]
+	fn a_vanished_peer_is_a_recv_error() {
+		let (server, client) = session::<Rx<u32, End>>();
+		drop(client);
+		assert_eq!(server.recv().err(), Some(RecvError::Disconnected));
+	}
+}
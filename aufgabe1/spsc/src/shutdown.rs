@@ -0,0 +1,209 @@
+//! One switch to shut down a whole group of channel-connected threads.
+//!
+//! Attach a `ShutdownToken` to the consumers of every channel involved and
+//! call `trigger()` on any clone of it. From then on `recv()` on those
+//! consumers still hands out whatever is queued, but returns
+//! `RecvError::Shutdown` instead of waiting for more, including in threads
+//! that are blocked in `recv()` at that moment. `try_recv()` and
+//! `recv_timeout()` report it as a disconnect.
+//!
+//! A thread that stops on `Shutdown` and drops its producers disconnects the
+//! next stage. So for a chain of threads, attach the token to the channel at
+//! the front only. The chain then drains front to back: every stage finishes
+//! what it already has, and the one after it stops on the disconnect once it
+//! got all of it. A stage attached to the token itself would stop as soon as
+//! its queue runs empty, even if the stage before it is still working.
+
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::Consumer;
+
+// Wakes up one attached channel.
+type Waker = Box<dyn Fn() + Send>;
+
+struct Inner {
+	triggered: AtomicBool,
+	next_id: AtomicUsize,
+	// one per attached channel, removed when the channel goes away
+	wakers: Mutex<Vec<(usize, Waker)>>,
+}
+
+#[derive(Clone)]
+pub struct ShutdownToken {
+	inner: Arc<Inner>,
+}
+
+impl ShutdownToken {
+	pub fn new() -> Self {
+		ShutdownToken {
+			inner: Arc::new(Inner { triggered: AtomicBool::new(false), next_id: AtomicUsize::new(0), wakers: Mutex::new(Vec::new()) }),
+		}
+	}
+
+	/// Shuts down every attached channel. Triggering twice does nothing.
+	pub fn trigger(&self) {
+		if self.inner.triggered.swap(true, Ordering::SeqCst) {
+			return;
+		}
+		// Called without holding the list: a waker may drop the last handle
+		// to its channel, whose Attachment then wants the list itself.
+		let wakers = mem::take(&mut *self.inner.wakers.lock().unwrap());
+		for (_, wake) in wakers {
+			wake();
+		}
+	}
+
+	pub fn is_triggered(&self) -> bool {
+		self.inner.triggered.load(Ordering::SeqCst)
+	}
+}
+
+impl Default for ShutdownToken {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+// The token as a channel holds it. Takes the channel's waker off the token's
+// list when the channel goes away or gets another token.
+pub(crate) struct Attachment {
+	token: ShutdownToken,
+	id: usize,
+}
+
+impl Attachment {
+	// For a channel that is shut down from the start, see Producer::pass_on().
+	pub(crate) fn triggered() -> Self {
+		let token = ShutdownToken::new();
+		token.trigger();
+		Attachment { token, id: 0 }
+	}
+
+	pub(crate) fn is_triggered(&self) -> bool {
+		self.token.is_triggered()
+	}
+}
+
+impl Drop for Attachment {
+	fn drop(&mut self) {
+		self.token.inner.wakers.lock().unwrap().retain(|&(id, _)| id != self.id);
+	}
+}
+
+/// Makes the channel `cx` belongs to stop on `token`. If the token was
+/// already triggered, the channel is shut down right away.
+pub fn attach<T: Send + 'static>(cx: &Consumer<T>, token: &ShutdownToken) {
+	let id = token.inner.next_id.fetch_add(1, Ordering::Relaxed);
+	let previous = match cx.shared.lock() {
+		Ok(mut state) => state.shutdown.replace(Attachment { token: token.clone(), id }),
+		Err(_) => None,
+	};
+	// outside the channel lock, trigger() takes the locks the other way round
+	drop(previous);
+
+	let shared = Arc::downgrade(&cx.shared);
+	let wake = move || {
+		if let Some(shared) = shared.upgrade() {
+			// taking the lock makes sure a recv() that has not seen the token
+			// yet is already waiting and gets the notification
+			if let Ok(state) = shared.lock() {
+				state.notify_watchers();
+			}
+			shared.not_empty.notify_all();
+		}
+	};
+
+	let mut wakers = token.inner.wakers.lock().unwrap();
+	if token.is_triggered() {
+		// trigger() already went through the list
+		drop(wakers);
+		wake();
+	} else {
+		wakers.push((id, Box::new(wake)));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::{channel, RecvError, TryRecvError};
+	use std::thread;
+	use std::time::Duration;
+
+	#[test]
+	fn blocked_recv_returns_shutdown() {
+		let token = ShutdownToken::new();
+		let (px, cx) = channel::<u32>(4);
+		attach(&cx, &token);
+
+		let consumer_thread = thread::spawn(move || cx.recv());
+		thread::sleep(Duration::from_millis(10));
+		token.trigger();
+
+		assert_eq!(consumer_thread.join().unwrap(), Err(RecvError::Shutdown));
+		drop(px);
+	}
+
+	#[test]
+	fn queued_values_are_drained_first() {
+		let token = ShutdownToken::new();
+		token.trigger();
+		let (px, cx) = channel::<u32>(4);
+		px.send(1).unwrap();
+		px.send(2).unwrap();
+		attach(&cx, &token);
+
+		assert_eq!(cx.recv(), Ok(1));
+		assert_eq!(cx.recv(), Ok(2));
+		assert_eq!(cx.recv(), Err(RecvError::Shutdown));
+		assert_eq!(cx.try_recv(), Err(TryRecvError::Disconnected));
+	}
+
+	#[test]
+	fn dropped_channels_leave_the_token() {
+		let token = ShutdownToken::new();
+		let (_px, cx) = channel::<u32>(4);
+		attach(&cx, &token);
+		for _ in 0..100 {
+			let (_px, cx) = channel::<u32>(4);
+			attach(&cx, &token);
+		}
+		assert_eq!(token.inner.wakers.lock().unwrap().len(), 1);
+
+		// attaching again replaces the old registration
+		attach(&cx, &ShutdownToken::new());
+		assert!(token.inner.wakers.lock().unwrap().is_empty());
+	}
+
+	#[test]
+	fn a_pipeline_stops_front_to_back() {
+		let token = ShutdownToken::new();
+		let (source, first) = channel::<u32>(16);
+		let (middle, second) = channel::<u32>(16);
+		attach(&first, &token);
+
+		let double = thread::spawn(move || {
+			while let Ok(v) = first.recv() {
+				middle.send(v * 2).unwrap();
+			}
+		});
+		let sum = thread::spawn(move || {
+			let mut sum = 0;
+			while let Ok(v) = second.recv() {
+				sum += v;
+			}
+			sum
+		});
+
+		for i in 1..=10 {
+			source.send(i).unwrap();
+		}
+		token.trigger();
+		double.join().unwrap();
+		assert_eq!(sum.join().unwrap(), 110);
+		drop(source);
+	}
+}
@@ -0,0 +1,186 @@
+//! A queue a Unix signal handler may push to. A signal handler must not take
+//! a lock or allocate, since the code it interrupted may hold that very lock,
+//! so the channels with their Mutex are out. `SignalProducer::send()` only
+//! touches atomics and a slot that was allocated up front, which makes it
+//! async-signal-safe.
+//!
+//! The ring is the bounded queue by Dmitry Vyukov: every slot carries a
+//! sequence number that says whether it is free for the producer at a given
+//! position or holds a value for the consumer there. Producers claim a
+//! position with a compare-and-swap and never wait for each other, so a
+//! handler that interrupts another send(), even on the same thread, just
+//! claims the next slot. When the ring is full the value is handed back and
+//! counted as lost, a handler cannot wait for room.
+//!
+//! See https://www.1024cores.net/home/lock-free-algorithms/queues/bounded-mpmc-queue
+//!
+//! The receiving side is not woken up, waking a thread from a handler would
+//! need a pipe or an eventfd. The main thread polls `try_recv()`, in its
+//! event loop or after a blocking call returned with EINTR.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct Slot<T> {
+	// position + 1 once the value for `position` is in, position + capacity
+	// once it was taken and the slot is free for the next round
+	seq: AtomicUsize,
+	value: UnsafeCell<MaybeUninit<T>>,
+}
+
+struct Ring<T> {
+	slots: Box<[Slot<T>]>,
+	mask: usize,
+	// next position to take, only the consumer moves it
+	head: AtomicUsize,
+	// next position to claim
+	tail: AtomicUsize,
+	lost: AtomicUsize,
+}
+
+// A slot is written by the one producer that claimed its position and read
+// by the consumer only after the sequence number says the write is done.
+unsafe impl<T: Copy + Send> Sync for Ring<T> {}
+
+/// The pushing side, safe to use from a signal handler. Can be cloned.
+pub struct SignalProducer<T: Copy + Send> {
+	ring: Arc<Ring<T>>,
+}
+
+/// The receiving side, for a single thread.
+pub struct SignalConsumer<T: Copy + Send> {
+	ring: Arc<Ring<T>>,
+}
+
+/// Creates a ring with room for `capacity` values, rounded up to a power of
+/// two. All memory is allocated here.
+pub fn signal_channel<T: Copy + Send>(capacity: usize) -> (SignalProducer<T>, SignalConsumer<T>) {
+	let capacity = capacity.max(2).next_power_of_two();
+	let slots = (0..capacity).map(|i| Slot { seq: AtomicUsize::new(i), value: UnsafeCell::new(MaybeUninit::uninit()) }).collect();
+	let ring = Arc::new(Ring {
+		slots,
+		mask: capacity - 1,
+		head: AtomicUsize::new(0),
+		tail: AtomicUsize::new(0),
+		lost: AtomicUsize::new(0),
+	});
+	(SignalProducer { ring: ring.clone() }, SignalConsumer { ring })
+}
+
+impl<T: Copy + Send> SignalProducer<T> {
+	/// Queues `value` without blocking, locking or allocating. Hands it back
+	/// if the ring is full.
+	pub fn send(&self, value: T) -> Result<(), T> {
+		let ring = &*self.ring;
+		let mut pos = ring.tail.load(Ordering::Relaxed);
+		loop {
+			let slot = &ring.slots[pos & ring.mask];
+			let seq = slot.seq.load(Ordering::Acquire);
+			if seq == pos {
+				// free for this position, try to claim it
+				match ring.tail.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+					Ok(_) => {
+						unsafe { (*slot.value.get()).write(value) };
+						slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+						return Ok(());
+					}
+					Err(current) => pos = current,
+				}
+			} else if (seq.wrapping_sub(pos) as isize) < 0 {
+				// the slot still holds the value from one round ago
+				ring.lost.fetch_add(1, Ordering::Relaxed);
+				return Err(value);
+			} else {
+				// another producer got this position first
+				pos = ring.tail.load(Ordering::Relaxed);
+			}
+		}
+	}
+}
+
+impl<T: Copy + Send> Clone for SignalProducer<T> {
+	fn clone(&self) -> Self {
+		SignalProducer { ring: self.ring.clone() }
+	}
+}
+
+impl<T: Copy + Send> SignalConsumer<T> {
+	/// The oldest value, or None if there is none yet. A value whose send()
+	/// is still in progress counts as not there yet, and so does everything
+	/// behind it.
+	pub fn try_recv(&mut self) -> Option<T> {
+		let ring = &*self.ring;
+		let pos = ring.head.load(Ordering::Relaxed);
+		let slot = &ring.slots[pos & ring.mask];
+		if slot.seq.load(Ordering::Acquire) != pos.wrapping_add(1) {
+			return None;
+		}
+		let value = unsafe { (*slot.value.get()).assume_init() };
+		ring.head.store(pos.wrapping_add(1), Ordering::Relaxed);
+		slot.seq.store(pos.wrapping_add(ring.mask + 1), Ordering::Release);
+		Some(value)
+	}
+
+	/// How many values were handed back to a producer because the ring was
+	/// full.
+	pub fn lost(&self) -> usize {
+		self.ring.lost.load(Ordering::Relaxed)
+	}
+
+	pub fn capacity(&self) -> usize {
+		self.ring.mask + 1
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::thread;
+
+	#[cfg(miri)]
+	const ROUNDS: u32 = 100;
+	#[cfg(not(miri))]
+	const ROUNDS: u32 = 10000;
+
+	#[test]
+	fn full_ring_hands_the_value_back() {
+		let (px, mut cx) = signal_channel(3);
+		assert_eq!(cx.capacity(), 4);
+		for i in 0..4 {
+			px.send(i).unwrap();
+		}
+		assert_eq!(px.send(4), Err(4));
+		assert_eq!(cx.lost(), 1);
+		assert_eq!(cx.try_recv(), Some(0));
+		px.send(5).unwrap();
+		let rest: Vec<_> = (0..4).filter_map(|_| cx.try_recv()).collect();
+		assert_eq!(rest, vec![1, 2, 3, 5]);
+		assert_eq!(cx.try_recv(), None);
+	}
+
+	#[test]
+	fn concurrent_producers_lose_nothing_they_were_not_told_about() {
+		let (px, mut cx) = signal_channel::<(u32, u32)>(64);
+		let producers: Vec<_> = (0..3).map(|p| {
+			let px = px.clone();
+			thread::spawn(move || (0..ROUNDS).filter(|&i| px.send((p, i)).is_ok()).count())
+		}).collect();
+
+		let mut last = [None; 3];
+		let mut received = 0;
+		while !producers.iter().all(|p| p.is_finished()) || received < ROUNDS as usize * 3 - cx.lost() {
+			if let Some((p, i)) = cx.try_recv() {
+				// every producer's values arrive in order
+				assert!(last[p as usize].is_none_or(|l| l < i));
+				last[p as usize] = Some(i);
+				received += 1;
+			}
+		}
+		let sent: usize = producers.into_iter().map(|p| p.join().unwrap()).sum();
+		assert_eq!(received, sent);
+		assert_eq!(sent + cx.lost(), ROUNDS as usize * 3);
+	}
+}
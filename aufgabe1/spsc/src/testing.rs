@@ -0,0 +1,78 @@
+//! Helpers for tests of code built on the channels, behind the `test-util`
+//! feature. Enable it from `[dev-dependencies]` only:
+//!
+//! ```toml
+//! spsc = { path = "...", features = ["test-util"] }
+//! ```
+//!
+//! `assert_drained()` catches values that were sent but never received: once
+//! it was called on a channel, the channel panics when its last producer and
+//! consumer are gone while values are still queued. The panic happens in
+//! whichever thread drops the last handle, so a test should join the threads
+//! it started to see it.
+
+use super::Consumer;
+
+/// How many values went into and came out of a channel so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+	pub enqueued: u64,
+	pub dequeued: u64,
+}
+
+impl Counts {
+	pub fn in_flight(&self) -> u64 {
+		self.enqueued - self.dequeued
+	}
+}
+
+pub fn counts<T: Send>(cx: &Consumer<T>) -> Counts {
+	let state = cx.shared.lock().unwrap_or_else(|e| e.into_inner());
+	Counts { enqueued: state.sent, dequeued: state.received }
+}
+
+/// Makes dropping the channel `cx` belongs to with values still queued
+/// panic. Values handed to an `on_drop_drain()` callback count as consumed.
+pub fn assert_drained<T: Send>(cx: &Consumer<T>) {
+	cx.shared.lock().unwrap_or_else(|e| e.into_inner()).must_drain = true;
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::channel;
+	use std::panic;
+
+	#[test]
+	fn counts_follow_the_traffic() {
+		let (px, cx) = channel::<u32>(4);
+		px.send(1).unwrap();
+		px.send(2).unwrap();
+		cx.recv().unwrap();
+		assert_eq!(counts(&cx), Counts { enqueued: 2, dequeued: 1 });
+		assert_eq!(counts(&cx).in_flight(), 1);
+	}
+
+	#[test]
+	fn drained_channel_drops_quietly() {
+		let (px, cx) = channel::<u32>(4);
+		assert_drained(&cx);
+		px.send(1).unwrap();
+		assert_eq!(cx.recv(), Ok(1));
+	}
+
+	#[test]
+	fn leftover_values_fail_the_test() {
+		let result = panic::catch_unwind(|| {
+			let (px, cx) = channel::<u32>(4);
+			assert_drained(&cx);
+			px.send(1).unwrap();
+			px.send(2).unwrap();
+			cx.recv().unwrap();
+		});
+		let payload = result.unwrap_err();
+		let message = payload.downcast_ref::<String>().unwrap();
+		assert!(message.contains("1 of 2"), "{}", message);
+	}
+}
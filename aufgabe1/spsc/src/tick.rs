@@ -0,0 +1,111 @@
+//! Periodic messages. `tick()` returns a consumer that receives the time a
+//! tick was due once every period, from a thread of its own, so periodic
+//! work can sit in a `Select` next to the data channels instead of in a loop
+//! around `sleep()`. Since a `Select` needs one message type for all
+//! sources, `tick_with()` turns the time into the caller's type first.
+//!
+//! There is no shared timer: every ticker has its own thread.
+//!
+//! A tick nobody picked up yet is not followed by another one: the channel
+//! has room for a single tick and the ticks in between are skipped, so a slow
+//! consumer does not find a backlog of them. The thread stops at the first
+//! tick after the consumer was dropped.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{sync_channel, Consumer, TrySendError};
+
+/// Receives the due time of a tick every `period`, starting one period from
+/// now. Panics if `period` is zero.
+pub fn tick(period: Duration) -> Consumer<Instant> {
+	tick_with(period, |due| due)
+}
+
+/// Like `tick()`, but receives `f(due)`.
+pub fn tick_with<T, F>(period: Duration, mut f: F) -> Consumer<T>
+	where T: Send + 'static, F: FnMut(Instant) -> T + Send + 'static
+{
+	assert!(period > Duration::from_secs(0), "tick_with() period must not be zero.");
+	let (px, cx) = sync_channel(1);
+
+	thread::spawn(move || {
+		let mut next = Instant::now() + period;
+		loop {
+			let now = Instant::now();
+			if next > now {
+				thread::sleep(next - now);
+			}
+			match px.try_send(f(next)) {
+				Ok(()) | Err(TrySendError::Full(_)) => {}
+				Err(_) => break,
+			}
+			// skip the ticks that are already overdue
+			next += period;
+			let now = Instant::now();
+			while next <= now {
+				next += period;
+			}
+		}
+	});
+	cx
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::channel;
+	use super::super::select::Select;
+	use std::panic;
+
+	#[test]
+	fn ticks_come_once_per_period() {
+		let ticks = tick(Duration::from_millis(5));
+		let start = Instant::now();
+		let first = ticks.recv().unwrap();
+		let second = ticks.recv().unwrap();
+		assert!(first >= start);
+		assert!(second - first >= Duration::from_millis(5));
+	}
+
+	#[test]
+	fn missed_ticks_do_not_pile_up() {
+		let ticks = tick(Duration::from_millis(1));
+		thread::sleep(Duration::from_millis(30));
+		assert_eq!(ticks.size().unwrap(), 1);
+	}
+
+	#[test]
+	fn zero_period_is_rejected() {
+		assert!(panic::catch_unwind(|| tick(Duration::from_secs(0))).is_err());
+	}
+
+	#[derive(Debug, PartialEq)]
+	enum Message {
+		Data(u32),
+		Tick,
+	}
+
+	#[test]
+	fn ticks_mix_with_data_in_a_select() {
+		let (px, cx) = channel(0);
+		let select = Select::new(vec![cx, tick_with(Duration::from_millis(2), |_| Message::Tick)]);
+
+		px.send(Message::Data(7)).unwrap();
+		let mut seen_tick = false;
+		let mut seen_data = false;
+		while !(seen_tick && seen_data) {
+			match select.recv().unwrap() {
+				(0, message) => {
+					assert_eq!(message, Message::Data(7));
+					seen_data = true;
+				}
+				(_, message) => {
+					assert_eq!(message, Message::Tick);
+					seen_tick = true;
+				}
+			}
+		}
+	}
+}
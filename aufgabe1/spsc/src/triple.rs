@@ -0,0 +1,151 @@
+//! A triple buffer for handing the latest value from one thread to another.
+//! Of the three buffers one belongs to the writer, one to the reader and one
+//! is the "back" buffer in between. Writing swaps the writer's buffer with the
+//! back buffer, reading swaps the back buffer with the reader's if it holds
+//! something new. Both sides only do a single atomic swap, so neither ever
+//! waits for the other. Older values that were never read are overwritten,
+//! which is what a control loop that only cares about the newest sample wants.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// The back buffer index lives in the low two bits, this bit says whether the
+// back buffer holds a value the reader has not seen yet.
+const DIRTY: usize = 0b100;
+const INDEX: usize = 0b011;
+
+struct Shared<T> {
+	buffers: [UnsafeCell<T>; 3],
+	back: AtomicUsize,
+}
+
+// Each buffer is only ever touched by whoever owns its index, and ownership
+// changes hands through the swap on `back`.
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+pub struct TripleBuffer<T: Send> {
+	shared: Arc<Shared<T>>,
+}
+
+/// The writing end, see `TripleBuffer::split()`.
+pub struct TripleInput<T: Send> {
+	shared: Arc<Shared<T>>,
+	index: usize,
+}
+
+/// The reading end, see `TripleBuffer::split()`.
+pub struct TripleOutput<T: Send> {
+	shared: Arc<Shared<T>>,
+	index: usize,
+}
+
+impl<T: Send + Clone> TripleBuffer<T> {
+	/// All three buffers start out as a copy of `initial`.
+	pub fn new(initial: T) -> Self {
+		TripleBuffer {
+			shared: Arc::new(Shared {
+				buffers: [
+					UnsafeCell::new(initial.clone()),
+					UnsafeCell::new(initial.clone()),
+					UnsafeCell::new(initial),
+				],
+				back: AtomicUsize::new(1),
+			}),
+		}
+	}
+}
+
+impl<T: Send> TripleBuffer<T> {
+	pub fn split(self) -> (TripleInput<T>, TripleOutput<T>) {
+		(
+			TripleInput { shared: self.shared.clone(), index: 0 },
+			TripleOutput { shared: self.shared, index: 2 },
+		)
+	}
+}
+
+impl<T: Send> TripleInput<T> {
+	/// Publishes `value` as the newest one.
+	pub fn write(&mut self, value: T) {
+		*self.input_buffer() = value;
+		self.publish();
+	}
+
+	/// The buffer the next `publish()` hands over, for updating a large
+	/// value in place. It holds whatever was in it the last time the writer
+	/// owned it, not necessarily the last published value.
+	pub fn input_buffer(&mut self) -> &mut T {
+		unsafe { &mut *self.shared.buffers[self.index].get() }
+	}
+
+	pub fn publish(&mut self) {
+		let old = self.shared.back.swap(self.index | DIRTY, Ordering::AcqRel);
+		self.index = old & INDEX;
+	}
+}
+
+impl<T: Send> TripleOutput<T> {
+	/// Whether a value was published since the last `read()`.
+	pub fn updated(&self) -> bool {
+		self.shared.back.load(Ordering::Relaxed) & DIRTY != 0
+	}
+
+	/// Returns the newest published value, or the previous one again if
+	/// nothing new was published.
+	pub fn read(&mut self) -> &T {
+		if self.updated() {
+			let old = self.shared.back.swap(self.index, Ordering::AcqRel);
+			self.index = old & INDEX;
+		}
+		unsafe { &*self.shared.buffers[self.index].get() }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::thread;
+
+	// Miri is a few thousand times slower, keep its runs short
+	const ROUNDS: u64 = if cfg!(miri) { 500 } else { 100000 };
+
+	#[test]
+	fn reader_gets_the_newest_value() {
+		let (mut input, mut output) = TripleBuffer::new(0u32).split();
+		assert!(!output.updated());
+		assert_eq!(*output.read(), 0);
+
+		input.write(1);
+		input.write(2);
+		assert!(output.updated());
+		assert_eq!(*output.read(), 2);
+		assert!(!output.updated());
+		assert_eq!(*output.read(), 2);
+
+		*input.input_buffer() = 3;
+		input.publish();
+		assert_eq!(*output.read(), 3);
+	}
+
+	#[test]
+	fn threaded_values_never_go_backwards() {
+		let (mut input, mut output) = TripleBuffer::new((0u64, 0u64)).split();
+
+		let writer = thread::spawn(move || {
+			for i in 1..ROUNDS {
+				input.write((i, i * 3));
+			}
+		});
+
+		let mut last = 0;
+		while last < ROUNDS - 1 {
+			let &(a, b) = output.read();
+			assert_eq!(b, a * 3);
+			assert!(a >= last);
+			last = a;
+		}
+		writer.join().unwrap();
+	}
+}
@@ -0,0 +1,96 @@
+//! Fill-level notifications. `watch()` gives a channel a high and a low
+//! watermark and returns a consumer of `Watermark` events: `High` once the
+//! queue has grown to the high watermark, `Low` once it has shrunk back to
+//! the low one. Between the two nothing is reported, so a queue hovering
+//! around one mark does not flood the events.
+//!
+//! A producer can slow down on `High` and speed up again on `Low`, long
+//! before a bounded channel would block it. The events are sent from under
+//! the channel lock into an unbounded channel, so watching never blocks the
+//! watched channel, and the events consumer disconnects once the watched
+//! channel is gone.
+
+use super::{channel, Consumer, Producer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watermark {
+	/// The queue holds `high` values or more.
+	High,
+	/// The queue is back down to `low` values or fewer.
+	Low,
+}
+
+pub(crate) struct Watermarks {
+	high: usize,
+	low: usize,
+	// whether High was the last event
+	above: bool,
+	events: Producer<Watermark>,
+}
+
+impl Watermarks {
+	// Called with the queue length after every change.
+	pub(crate) fn check(&mut self, len: usize) {
+		let event = if !self.above && len >= self.high {
+			Watermark::High
+		} else if self.above && len <= self.low {
+			Watermark::Low
+		} else {
+			return;
+		};
+		self.above = event == Watermark::High;
+		// nobody might be listening anymore
+		let _ = self.events.send(event);
+	}
+}
+
+/// Reports the channel `px` belongs to crossing `high` and `low`. Replaces
+/// earlier watermarks, whose events consumer then disconnects.
+pub fn watch<T: Send>(px: &Producer<T>, high: usize, low: usize) -> Consumer<Watermark> {
+	assert!(low < high, "watermark::watch() low watermark must be below the high one.");
+	let (events, cx) = channel(0);
+	let mut state = px.shared.lock().unwrap();
+	let mut marks = Watermarks { high, low, above: false, events };
+	marks.check(state.queue.len());
+	state.watermarks = Some(marks);
+	cx
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use super::super::{sync_channel, TryRecvError};
+
+	#[test]
+	fn crossings_are_reported_once() {
+		let (px, cx) = sync_channel(10);
+		let events = watch(&px, 4, 1);
+
+		for i in 0..6 {
+			px.send(i).unwrap();
+		}
+		assert_eq!(events.try_recv(), Ok(Watermark::High));
+		assert_eq!(events.try_recv(), Err(TryRecvError::Empty));
+
+		// 6 -> 2, still above the low watermark
+		for _ in 0..4 {
+			cx.recv().unwrap();
+		}
+		assert_eq!(events.try_recv(), Err(TryRecvError::Empty));
+		cx.recv().unwrap();
+		assert_eq!(events.try_recv(), Ok(Watermark::Low));
+
+		px.send(6).unwrap();
+		assert_eq!(events.try_recv(), Err(TryRecvError::Empty));
+	}
+
+	#[test]
+	fn events_disconnect_with_the_channel() {
+		let (px, cx) = channel::<u32>(0);
+		let events = watch(&px, 2, 0);
+		drop(px);
+		drop(cx);
+		assert_eq!(events.try_recv(), Err(TryRecvError::Disconnected));
+	}
+}